@@ -0,0 +1,121 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use maxminddb::geoip2;
+use mmdb_grpc::locales::Locales;
+use mmdb_grpc::proto::geoip2::Message_Locale;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A record of a city database, with names in every locale the GeoIP2 databases have.
+const CITY: &str = r#"{
+    "city": {"geoname_id": 1850147, "names": {
+        "de": "Tokio", "en": "Tokyo", "es": "Tokio", "fr": "Tokyo", "ja": "東京",
+        "pt-BR": "Tóquio", "ru": "Токио", "zh-CN": "东京"
+    }},
+    "continent": {"code": "AS", "geoname_id": 6255147, "names": {
+        "de": "Asien", "en": "Asia", "es": "Asia", "fr": "Asie", "ja": "アジア",
+        "pt-BR": "Ásia", "ru": "Азия", "zh-CN": "亚洲"
+    }},
+    "country": {"geoname_id": 1861060, "iso_code": "JP", "names": {
+        "de": "Japan", "en": "Japan", "es": "Japón", "fr": "Japon", "ja": "日本",
+        "pt-BR": "Japão", "ru": "Япония", "zh-CN": "日本"
+    }},
+    "location": {"accuracy_radius": 500, "latitude": 35.6893, "longitude": 139.6899, "time_zone": "Asia/Tokyo"},
+    "postal": {"code": "100-0001"},
+    "registered_country": {"geoname_id": 1861060, "iso_code": "JP", "names": {
+        "de": "Japan", "en": "Japan", "es": "Japón", "fr": "Japon", "ja": "日本",
+        "pt-BR": "Japão", "ru": "Япония", "zh-CN": "日本"
+    }},
+    "subdivisions": [{"geoname_id": 1850144, "iso_code": "13", "names": {
+        "de": "Tokio", "en": "Tokyo", "es": "Tokio", "fr": "Préfecture de Tokyo", "ja": "東京都",
+        "pt-BR": "Tóquio", "ru": "Токио", "zh-CN": "东京都"
+    }}]
+}"#;
+
+fn filters() -> [(&'static str, Vec<Message_Locale>); 2] {
+    [
+        ("all_locales", vec![]),
+        ("single_locale", vec![Message_Locale::ENGLISH]),
+    ]
+}
+
+fn convert(c: &mut Criterion) {
+    let city: geoip2::City = serde_json::from_str(CITY).unwrap();
+    let mut group = c.benchmark_group("convert");
+    for (name, locales) in filters() {
+        let locales = locales.iter().collect::<Locales>();
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || city.clone(),
+                |city| mmdb_grpc::convert(city, locales),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// The names of every place in the record, which a reply carries filtered by the requested locales.
+fn names<'a>(city: &'a geoip2::City<'a>) -> Vec<&'a BTreeMap<&'a str, &'a str>> {
+    let subdivisions = city.subdivisions.iter().flatten().filter_map(|s| s.names.as_ref());
+    [
+        city.city.as_ref().and_then(|c| c.names.as_ref()),
+        city.continent.as_ref().and_then(|c| c.names.as_ref()),
+        city.country.as_ref().and_then(|c| c.names.as_ref()),
+        city.registered_country.as_ref().and_then(|c| c.names.as_ref()),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(subdivisions)
+    .collect()
+}
+
+/// The filter the locales were held in before `Locales`, kept as the baseline: a set of codes built for
+/// every lookup, each name key copied to be looked up in it.
+fn filter_with_hash_set(names: &BTreeMap<&str, &str>, filter: &HashSet<String>) -> HashMap<String, String> {
+    let cap = if filter.is_empty() { names.len() } else { filter.len() };
+    let mut h = HashMap::with_capacity(cap);
+    for (k, v) in names.iter() {
+        let k = k.to_string();
+        if filter.is_empty() || filter.contains(&k) {
+            h.insert(k, v.to_string());
+        }
+    }
+    h
+}
+
+fn filter_with_locales(names: &BTreeMap<&str, &str>, filter: Locales) -> HashMap<String, String> {
+    let mut h = HashMap::with_capacity(filter.capacity(names.len()));
+    for (k, v) in names.iter() {
+        if filter.contains(k) {
+            h.insert(k.to_string(), v.to_string());
+        }
+    }
+    h
+}
+
+fn filter_names(c: &mut Criterion) {
+    let city: geoip2::City = serde_json::from_str(CITY).unwrap();
+    let names = names(&city);
+    let mut group = c.benchmark_group("filter_names");
+    for (name, locales) in filters() {
+        group.bench_function(format!("hash_set/{}", name), |b| {
+            b.iter(|| {
+                let filter = locales.iter().map(|l| l.to_string()).collect::<HashSet<_>>();
+                for n in names.iter() {
+                    black_box(filter_with_hash_set(n, &filter));
+                }
+            })
+        });
+        group.bench_function(format!("locales/{}", name), |b| {
+            b.iter(|| {
+                let filter = locales.iter().collect::<Locales>();
+                for n in names.iter() {
+                    black_box(filter_with_locales(n, filter));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, convert, filter_names);
+criterion_main!(benches);
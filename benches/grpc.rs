@@ -0,0 +1,69 @@
+#[path = "../src/fixture.rs"]
+mod fixture;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use grpcio::{ChannelBuilder, EnvBuilder, Environment, Server, ServerBuilder, ServerCredentials};
+use maxminddb::MaxMindDBError;
+use mmdb_grpc::cache::ReplyCache;
+use mmdb_grpc::database::{Database, Version};
+use mmdb_grpc::proto::geoip2::{Message, Message_Locale};
+use mmdb_grpc::proto::geoip2_grpc::GeoIpClient;
+use mmdb_grpc::{create_geo_ip, CityService};
+use spin::RwLock;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+type Reloader = fn() -> Result<Version<Arc<[u8]>>, MaxMindDBError>;
+
+/// Starts a server for the fixture database on a free local port, and connects a client to it.
+fn serve(cache: Option<ReplyCache>) -> (Server, GeoIpClient) {
+    let version = Version::from_source("bench.mmdb".to_string(), Arc::from(fixture::database(1))).unwrap();
+    let db = Arc::new(RwLock::new(Database::new(version, 1)));
+    let service = CityService::<_, Reloader>::new(db, None, false, cache);
+
+    let env = Arc::new(Environment::new(2));
+    let mut server = ServerBuilder::new(env)
+        .register_service(create_geo_ip(service))
+        .build()
+        .unwrap();
+    let port = server
+        .add_listening_port("127.0.0.1:0", ServerCredentials::insecure())
+        .unwrap();
+    server.start();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    (server, GeoIpClient::new(ch))
+}
+
+/// Unary `Lookup` calls answered by an in-process server, one at a time.
+fn round_trip(c: &mut Criterion) {
+    let addresses = fixture::addresses(1024);
+    let cases = [
+        ("all_locales", vec![], None),
+        ("single_locale", vec![Message_Locale::ENGLISH], None),
+        (
+            "cached",
+            vec![],
+            Some(ReplyCache::new(NonZeroUsize::new(fixture::RECORDS).unwrap())),
+        ),
+    ];
+    let mut group = c.benchmark_group("grpc");
+    for (name, locales, cache) in cases {
+        let (mut server, client) = serve(cache);
+        group.bench_function(name, |b| {
+            let mut ips = addresses.iter().cycle();
+            b.iter(|| {
+                let mut msg = Message::default();
+                msg.set_ip(ips.next().unwrap().clone());
+                msg.set_locales(locales.clone());
+                client.lookup(&msg).unwrap()
+            })
+        });
+        futures::executor::block_on(server.shutdown()).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+#[path = "../src/fixture.rs"]
+mod fixture;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use maxminddb::{geoip2, Reader};
+use mmdb_grpc::database::{Database, Version};
+use mmdb_grpc::locales::Locales;
+use mmdb_grpc::proto::geoip2::Message_Locale;
+use spin::RwLock;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn filters() -> Vec<(&'static str, Locales)> {
+    vec![
+        ("all_locales", Locales::all()),
+        ("single_locale", [Message_Locale::ENGLISH].iter().collect()),
+        (
+            "three_locales",
+            [
+                Message_Locale::ENGLISH,
+                Message_Locale::JAPANESE,
+                Message_Locale::FRENCH,
+            ]
+            .iter()
+            .collect(),
+        ),
+    ]
+}
+
+/// What a `Lookup` call does between receiving the request and replying, as in `CityService` without a cache.
+fn lookup(c: &mut Criterion) {
+    let reader = Reader::from_source(fixture::database(1)).unwrap();
+    let addresses = fixture::addresses(1024);
+    let mut group = c.benchmark_group("lookup");
+    for (name, locales) in filters() {
+        group.bench_function(name, |b| {
+            let mut ips = addresses.iter().cycle();
+            b.iter(|| {
+                let addr: IpAddr = ips.next().unwrap().parse().unwrap();
+                let city = reader.lookup::<geoip2::City>(addr).unwrap();
+                mmdb_grpc::convert(city, locales)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn version(data: &Arc<[u8]>) -> Version<Arc<[u8]>> {
+    Version::from_source("bench.mmdb".to_string(), data.clone()).unwrap()
+}
+
+/// Lookups made by `readers` threads sharing the database, while another thread reloads it every millisecond.
+fn readers_during_reload(c: &mut Criterion) {
+    let data: Arc<[u8]> = Arc::from(fixture::database(1));
+    let db = Arc::new(RwLock::new(Database::new(version(&data), 2)));
+    let addresses = Arc::new(fixture::addresses(1024));
+    let mut group = c.benchmark_group("readers_during_reload");
+    for readers in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::from_parameter(readers), &readers, |b, &readers| {
+            b.iter_custom(|iters| {
+                let stop = Arc::new(AtomicBool::new(false));
+                let reloader = {
+                    let (db, data, stop) = (db.clone(), data.clone(), stop.clone());
+                    thread::spawn(move || {
+                        while !stop.load(Ordering::Relaxed) {
+                            let loaded = version(&data);
+                            db.write().reload(Ok(loaded)).unwrap();
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    })
+                };
+
+                let started = Instant::now();
+                let handles: Vec<_> = (0..readers)
+                    .map(|reader| {
+                        let (db, addresses) = (db.clone(), addresses.clone());
+                        thread::spawn(move || {
+                            for i in 0..iters as usize {
+                                let addr: IpAddr = addresses[(reader + i) % addresses.len()].parse().unwrap();
+                                let db = db.read();
+                                let city = db.reader().unwrap().lookup::<geoip2::City>(addr).unwrap();
+                                black_box(mmdb_grpc::convert(city, Locales::all()));
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+                let elapsed = started.elapsed();
+
+                stop.store(true, Ordering::Relaxed);
+                reloader.join().unwrap();
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup, readers_during_reload);
+criterion_main!(benches);
@@ -5,13 +5,25 @@ use std::path::Path;
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let proto_root = "protos";
+    let local_proto_root = "proto";
     let proto_out = Path::new(&out_dir).join("proto");
     let geoip2_proto = "protos/geoip2.proto";
+    let admin_proto = "proto/admin.proto";
+    let lookup_proto = "proto/lookup.proto";
+    let status_proto = "proto/google/rpc/status.proto";
+    let error_details_proto = "proto/google/rpc/error_details.proto";
     fs::create_dir_all(&proto_out).unwrap();
     protobuf_build::Builder::new()
-        .includes(&[proto_root.to_owned()])
-        .files(&[geoip2_proto])
+        .includes(&[proto_root.to_owned(), local_proto_root.to_owned()])
+        .files(&[
+            geoip2_proto,
+            admin_proto,
+            lookup_proto,
+            status_proto,
+            error_details_proto,
+        ])
         .out_dir(proto_out.as_path().display().to_string())
         .generate();
     println!("cargo:rerun-if-changed={}", proto_root);
+    println!("cargo:rerun-if-changed={}", local_proto_root);
 }
@@ -1,8 +1,10 @@
 use clap::Parser;
 use grpcio::{ChannelBuilder, EnvBuilder};
 use log::{error, info};
+use mmdb_grpc::proto::admin_grpc::AdminClient;
 use mmdb_grpc::proto::geoip2::*;
 use mmdb_grpc::proto::geoip2_grpc::GeoIpClient;
+use mmdb_grpc::{auth, tls};
 use std::sync::Arc;
 
 #[derive(Parser)]
@@ -14,6 +16,18 @@ struct Opts {
     host: String,
     #[clap(short = 'P', long = "port", value_parser, default_value = "50000")]
     port: u16,
+    #[clap(long = "tls", value_parser)]
+    tls: bool,
+    #[clap(long = "tls-ca", value_parser)]
+    tls_ca: Option<String>,
+    #[clap(long = "tls-cert", value_parser, requires = "tls_key")]
+    tls_cert: Option<String>,
+    #[clap(long = "tls-key", value_parser, requires = "tls_cert")]
+    tls_key: Option<String>,
+    #[clap(long = "tls-server-name", value_parser)]
+    tls_server_name: Option<String>,
+    #[clap(long = "token", value_parser)]
+    token: Option<String>,
 }
 
 impl Opts {
@@ -23,6 +37,9 @@ impl Opts {
     fn host(&self) -> &String {
         &self.host
     }
+    fn tls(&self) -> bool {
+        self.tls || self.tls_ca.is_some() || self.tls_cert.is_some()
+    }
 }
 
 fn main() {
@@ -31,13 +48,25 @@ fn main() {
     let opts: Opts = Opts::parse();
 
     let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(format!("{}:{}", opts.host(), opts.port).as_ref());
-    let client = GeoIpClient::new(ch);
+    let addr = format!("{}:{}", opts.host(), opts.port);
+    let mut builder = ChannelBuilder::new(env);
+    if let Some(ref name) = opts.tls_server_name {
+        builder = builder.override_ssl_target(name.as_str());
+    }
+    let ch = if opts.tls() {
+        let creds =
+            tls::channel_credentials(opts.tls_ca.as_ref(), opts.tls_cert.as_ref(), opts.tls_key.as_ref()).unwrap();
+        builder.secure_connect(addr.as_str(), creds)
+    } else {
+        builder.connect(addr.as_str())
+    };
+    let client = GeoIpClient::new(ch.clone());
+    let admin = AdminClient::new(ch);
 
     let mut msg = Message::default();
     msg.set_ip(opts.ip().clone());
 
-    match client.lookup(&msg) {
+    match client.lookup_opt(&msg, auth::call_option(opts.token.as_deref())) {
         Ok(entity) => {
             info!("requested message: {:?}, got city: {:?}", msg, entity);
         }
@@ -48,7 +77,7 @@ fn main() {
 
     msg.set_locales(vec![Message_Locale::ENGLISH, Message_Locale::JAPANESE]);
 
-    match client.lookup(&msg) {
+    match client.lookup_opt(&msg, auth::call_option(opts.token.as_deref())) {
         Ok(entity) => {
             info!("requested message: {:?}, got city: {:?}", msg, entity);
         }
@@ -57,12 +86,12 @@ fn main() {
         }
     }
 
-    match client.reload(&Empty::new()) {
+    match admin.reload_opt(&Empty::new(), auth::call_option(opts.token.as_deref())) {
         Ok(r) => info!("succeeded to reload: {:?}", r),
         Err(err) => error!("failed RPC, cause: {}", err),
     }
 
-    match client.metadata(&Empty::new()) {
+    match client.metadata_opt(&Empty::new(), auth::call_option(opts.token.as_deref())) {
         Ok(r) => info!("succeeded to request metadata: {:?}", r),
         Err(err) => error!("failed RPC, cause: {}", err),
     }
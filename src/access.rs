@@ -0,0 +1,358 @@
+use crate::metrics;
+use crate::proto::geoip2::Message_Locale;
+use crate::trace;
+use chrono::{SecondsFormat, Utc};
+use crossbeam_channel::{bounded, select, tick, Receiver, Sender};
+use grpcio::{RpcContext, RpcStatus, RpcStatusCode};
+use log::{error, warn};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const QUEUE_SIZE: usize = 8192;
+const WARN_INTERVAL: Duration = Duration::from_secs(5);
+
+static ACCESS_LOG: OnceLock<AccessLog> = OnceLock::new();
+
+/// Where the access log is written, given as `-` for stdout or a file path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Stdout,
+    File(PathBuf),
+}
+
+impl From<&str> for Destination {
+    fn from(s: &str) -> Destination {
+        if s == "-" {
+            Destination::Stdout
+        } else {
+            Destination::File(PathBuf::from(s))
+        }
+    }
+}
+
+/// Renames a log file to `<path>.1`, `<path>.1` to `<path>.2` and so on once it grows past `max_size`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+/// Parses sizes such as `1048576`, `512K`, `100M` or `1G`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" | "m" => 20,
+        "G" | "g" => 30,
+        _ => return Err(format!("The size must be like 100M but given '{}'", s)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("The size must be like 100M but given '{}'", s))
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    writer: LineWriter<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, rotation: Option<Rotation>) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            rotation,
+            writer: LineWriter::new(file),
+            written,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if let Some(rotation) = self.rotation {
+            if self.written > 0 && self.written + line.len() as u64 > rotation.max_size {
+                self.rotate(rotation.max_files)?;
+            }
+        }
+        self.writer.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self, max_files: usize) -> io::Result<()> {
+        self.writer.flush()?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let _ = fs::remove_file(rotated(max_files));
+        for n in (1..max_files).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        if max_files > 0 {
+            fs::rename(&self.path, rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        *self = RotatingFile::open(&self.path, self.rotation)?;
+        Ok(())
+    }
+}
+
+enum Output {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl Output {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(line),
+            Output::File(file) => file.write_line(line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().flush(),
+            Output::File(file) => file.writer.flush(),
+        }
+    }
+}
+
+enum Command {
+    Write(Vec<u8>),
+    Flush(Sender<()>),
+}
+
+/// Writes one JSON object per call from a background thread, so that the calls never wait on the disk.
+struct AccessLog {
+    sender: Sender<Command>,
+    anonymize: bool,
+    /// Lines dropped since the writer last warned about it.
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// Queues a line, or drops it when the writer is behind, which is only logged once per interval.
+    fn write(&self, line: Vec<u8>) {
+        if self.sender.try_send(Command::Write(line)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            metrics::observe_dropped_access_log();
+        }
+    }
+
+    fn flush(&self, timeout: Duration) {
+        let (sender, receiver) = bounded(1);
+        if self.sender.send_timeout(Command::Flush(sender), timeout).is_ok() {
+            let _ = receiver.recv_timeout(timeout);
+        }
+    }
+}
+
+fn run(receiver: Receiver<Command>, mut output: Output, dropped: &AtomicU64) {
+    let ticker = tick(WARN_INTERVAL);
+    loop {
+        select! {
+            recv(receiver) -> command => match command {
+                Ok(Command::Write(line)) => {
+                    if let Err(err) = output.write_line(&line) {
+                        error!("failed to write the access log, cause {}", err);
+                    }
+                }
+                Ok(Command::Flush(done)) => {
+                    if let Err(err) = output.flush() {
+                        error!("failed to write the access log, cause {}", err);
+                    }
+                    let _ = done.send(());
+                }
+                Err(_) => return,
+            },
+            recv(ticker) -> _ => {
+                let n = dropped.swap(0, Ordering::Relaxed);
+                if n > 0 {
+                    warn!("dropped {} access log lines in the last {:?}, the writer is behind", n, WARN_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+/// Starts writing the access log to `destination`, rotating files by `rotation`.
+///
+/// Calls are not logged until this is called, and only the first call has an effect.
+pub fn init(destination: &Destination, rotation: Option<Rotation>, anonymize: bool) -> io::Result<()> {
+    if ACCESS_LOG.get().is_some() {
+        return Ok(());
+    }
+    let output = match destination {
+        Destination::Stdout => Output::Stdout,
+        Destination::File(path) => Output::File(RotatingFile::open(path, rotation)?),
+    };
+    let (sender, receiver) = bounded(QUEUE_SIZE);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let counted = dropped.clone();
+    thread::spawn(move || run(receiver, output, &counted));
+    let _ = ACCESS_LOG.set(AccessLog {
+        sender,
+        anonymize,
+        dropped,
+    });
+    Ok(())
+}
+
+/// Waits up to `timeout` for the lines logged so far to be written.
+pub fn flush(timeout: Duration) {
+    if let Some(access_log) = ACCESS_LOG.get() {
+        access_log.flush(timeout);
+    }
+}
+
+/// Keeps the network of an address, dropping the host part: a /24 for IPv4 and a /48 for IPv6.
+fn anonymize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & !0xff)),
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !((1 << 80) - 1))),
+    }
+}
+
+/// Anonymizes the address of a peer such as `ipv4:192.0.2.123:5678`, dropping its port as well. Peers without an
+/// IP address, such as unix sockets, are kept as they are.
+fn anonymize_peer(peer: &str) -> String {
+    match trace::peer_address(peer).0.parse() {
+        Ok(IpAddr::V4(v4)) => format!("ipv4:{}", anonymize(IpAddr::V4(v4))),
+        Ok(IpAddr::V6(v6)) => format!("ipv6:[{}]", anonymize(IpAddr::V6(v6))),
+        Err(_) => peer.to_string(),
+    }
+}
+
+/// What a call asked for and got, beyond its method and status.
+#[derive(Default)]
+pub(crate) struct Details<'a> {
+    pub ip: Option<&'a str>,
+    pub locales: &'a [Message_Locale],
+    pub country: Option<&'a str>,
+}
+
+/// Logs a call of `geoip2.GeoIp`, `lookup.Lookup` or `admin.Admin` answered with `result`, `started` when it was
+/// received.
+pub(crate) fn log<R>(ctx: &RpcContext<'_>, result: &Result<R, RpcStatus>, details: Details<'_>, started: Instant) {
+    let access_log = match ACCESS_LOG.get() {
+        Some(access_log) => access_log,
+        None => return,
+    };
+    let latency = started.elapsed();
+
+    let method = String::from_utf8_lossy(ctx.method());
+    let code = match result {
+        Ok(_) => RpcStatusCode::OK,
+        Err(status) => status.code(),
+    };
+    let ip = details.ip.map(|ip| match ip.parse() {
+        Ok(parsed) if access_log.anonymize => anonymize(parsed).to_string(),
+        _ => ip.to_string(),
+    });
+    let peer = ctx.peer();
+    let entry = json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "peer": if access_log.anonymize { anonymize_peer(&peer) } else { peer },
+        "method": method.strip_prefix('/').unwrap_or(&*method),
+        "ip": ip,
+        "locales": details.locales.iter().map(|l| l.to_string()).collect::<Vec<_>>(),
+        "status": metrics::code_name(code),
+        "country": details.country.filter(|country| !country.is_empty()),
+        "latency_us": latency.as_micros() as u64,
+    });
+
+    let mut line = entry.to_string().into_bytes();
+    line.push(b'\n');
+    access_log.write(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize() {
+        assert_eq!(
+            anonymize("192.0.2.123".parse().unwrap()),
+            "192.0.2.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymize("2001:db8:85a3:8d3:1319:8a2e:370:7348".parse().unwrap()),
+            "2001:db8:85a3::".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_anonymize_peer() {
+        assert_eq!(anonymize_peer("ipv4:192.0.2.123:5678"), "ipv4:192.0.2.0");
+        assert_eq!(
+            anonymize_peer("ipv6:[2001:db8:85a3:8d3:1319:8a2e:370:7348]:5678"),
+            "ipv6:[2001:db8:85a3::]"
+        );
+        assert_eq!(anonymize_peer("unix:/run/mmdb.sock"), "unix:/run/mmdb.sock");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1048576"), Ok(1048576));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("100M"), Ok(100 << 20));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("100MB").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let rotation = Rotation {
+            max_size: 10,
+            max_files: 2,
+        };
+        let mut file = RotatingFile::open(&path, Some(rotation)).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.path().join("access.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.path().join("access.log.2")).unwrap(), "second\n");
+        assert!(!dir.path().join("access.log.3").exists());
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let output = Output::File(RotatingFile::open(&path, None).unwrap());
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+        let writer = thread::spawn(move || run(receiver, output, &AtomicU64::new(0)));
+        for line in ["first\n", "second\n"] {
+            sender.send(Command::Write(line.as_bytes().to_vec())).unwrap();
+        }
+        let (done, flushed) = bounded(1);
+        sender.send(Command::Flush(done)).unwrap();
+        flushed.recv().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        drop(sender);
+        writer.join().unwrap();
+    }
+}
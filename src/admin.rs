@@ -0,0 +1,194 @@
+use crate::access::{self, Details};
+use crate::convert_error;
+use crate::database::{Database, Version as LoadedVersion};
+use crate::metrics;
+use crate::proto::admin::*;
+use crate::proto::admin_grpc::*;
+use crate::proto::geoip2::{Empty, MetadataReply};
+use crate::trace::Span;
+use futures::prelude::*;
+use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use log::{error, info};
+use maxminddb::MaxMindDBError;
+use spin::RwLock;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Operations that change or inspect what the server serves, kept apart from the public lookups.
+#[derive(Clone)]
+pub struct AdminService<T, R>(Arc<RwLock<Database<T>>>, R)
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<LoadedVersion<T>, MaxMindDBError>;
+
+impl<T, R> AdminService<T, R>
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<LoadedVersion<T>, MaxMindDBError>,
+{
+    pub fn new(db: Arc<RwLock<Database<T>>>, reloader: R) -> AdminService<T, R> {
+        AdminService(db, reloader)
+    }
+}
+
+impl<T, R> Admin for AdminService<T, R>
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<LoadedVersion<T>, MaxMindDBError>,
+{
+    fn reload(&mut self, ctx: RpcContext<'_>, _req: Empty, sink: UnarySink<MetadataReply>) {
+        let started = Instant::now();
+        let result = reload(&self.0, &self.1);
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
+
+        let f = match result {
+            Ok(reply) => sink.success(reply),
+            Err(status) => sink.fail(status),
+        };
+
+        let f = f
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+
+        ctx.spawn(f)
+    }
+
+    fn versions(&mut self, ctx: RpcContext<'_>, _req: Empty, sink: UnarySink<VersionsReply>) {
+        let started = Instant::now();
+        let db = self.0.read();
+        let mut versions = Vec::new();
+        if let Some(v) = db.current() {
+            versions.push(Version::from(MVersion(v, true)));
+        }
+        versions.extend(db.previous().map(|v| Version::from(MVersion(v, false))));
+
+        let mut reply = VersionsReply::default();
+        reply.set_versions(::protobuf::RepeatedField::from_vec(versions));
+        let result = Ok(&reply);
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
+
+        let f = sink
+            .success(reply)
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+        ctx.spawn(f)
+    }
+
+    fn rollback(&mut self, ctx: RpcContext<'_>, req: RollbackRequest, sink: UnarySink<MetadataReply>) {
+        let started = Instant::now();
+        let build_epoch = req.get_build_epoch();
+        let result = {
+            let mut db = self.0.write();
+            match db.rollback(build_epoch) {
+                Some(v) => {
+                    info!("rolled back to {} built at {}", v.path(), build_epoch);
+                    Ok(MetadataReply::from(&v.reader().metadata))
+                }
+                None => Err(RpcStatus::with_message(
+                    RpcStatusCode::NOT_FOUND,
+                    format!("No previous version built at {} is kept", build_epoch),
+                )),
+            }
+        };
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
+
+        let f = match result {
+            Ok(reply) => sink.success(reply),
+            Err(status) => sink.fail(status),
+        };
+
+        let f = f
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+
+        ctx.spawn(f)
+    }
+
+    fn status(&mut self, ctx: RpcContext<'_>, _req: Empty, sink: UnarySink<StatusReply>) {
+        let started = Instant::now();
+        let reply = StatusReply::from(&*self.0.read());
+        let result = Ok(&reply);
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
+        let f = sink
+            .success(reply)
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+        ctx.spawn(f)
+    }
+}
+
+/// Serves the version `reloader` loads, as the `Reload` RPCs, SIGHUP and the updater do.
+pub fn reload<T, R>(db: &RwLock<Database<T>>, reloader: &R) -> Result<MetadataReply, RpcStatus>
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<LoadedVersion<T>, MaxMindDBError>,
+{
+    let mut span = Span::internal("reload");
+    let loaded = reloader();
+    let result = db
+        .write()
+        .reload(loaded)
+        .map(|version| MetadataReply::from(&version.reader().metadata))
+        .map_err(|err| convert_error(err, None, None));
+    match result {
+        Ok(ref reply) => {
+            span.set_attribute("mmdb.build_epoch", reply.get_build_epoch());
+            info!("succeeded to reload mmdb");
+        }
+        Err(ref status) => {
+            span.set_error(status.message());
+            error!("failed to reload mmdb, cause {}", status.message());
+        }
+    }
+    result
+}
+
+struct MVersion<'a, T: AsRef<[u8]>>(&'a LoadedVersion<T>, bool);
+
+impl<'a, T> From<MVersion<'a, T>> for Version
+where
+    T: AsRef<[u8]>,
+{
+    fn from(v: MVersion<'a, T>) -> Version {
+        let mut r = Version::default();
+        r.set_path(v.0.path().to_string());
+        r.set_loaded_at(unix_secs(v.0.loaded_at()));
+        r.set_current(v.1);
+        r.set_metadata(MetadataReply::from(&v.0.reader().metadata));
+        r
+    }
+}
+
+impl<T> From<&Database<T>> for StatusReply
+where
+    T: AsRef<[u8]>,
+{
+    fn from(db: &Database<T>) -> StatusReply {
+        let mut r = StatusReply::default();
+        if let Some(current) = db.current() {
+            r.set_path(current.path().to_string());
+            r.set_build_epoch(current.reader().metadata.build_epoch);
+            r.set_loaded_at(unix_secs(current.loaded_at()));
+            r.set_load_duration_millis(current.load_duration().as_millis() as u64);
+        }
+        let reloads = db.reloads();
+        if let Some(t) = reloads.last_attempt {
+            r.set_last_reload_attempt(unix_secs(t));
+        }
+        if let Some((t, msg)) = &reloads.last_error {
+            r.set_last_error_at(unix_secs(*t));
+            r.set_last_error(msg.clone());
+        }
+        r.set_reloads_succeeded(reloads.succeeded);
+        r.set_reloads_failed(reloads.failed);
+        r
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
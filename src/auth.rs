@@ -0,0 +1,229 @@
+use crate::metrics;
+use grpcio::{CallOption, CheckResult, MetadataBuilder, RpcContext, RpcStatus, RpcStatusCode, ServerChecker};
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use spin::RwLock;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The services anyone may call without a token, as load balancers and orchestrators probe them.
+const OPEN_SERVICES: [&[u8]; 1] = [b"/grpc.health.v1.Health/"];
+
+/// What a token allows its client to call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// `geoip2.GeoIp` except `Reload`, and `lookup.Lookup`.
+    Lookup,
+    /// `admin.Admin` and `geoip2.GeoIp/Reload`.
+    Reload,
+}
+
+impl Scope {
+    /// The scope required to call `method`, `None` for the services in [`OPEN_SERVICES`]. A service nobody
+    /// decided about requires `reload`, the scope of the operators.
+    fn required(method: &[u8]) -> Option<Scope> {
+        if OPEN_SERVICES.iter().any(|service| method.starts_with(service)) {
+            None
+        } else if method != b"/geoip2.GeoIp/Reload"
+            && (method.starts_with(b"/geoip2.GeoIp/") || method.starts_with(b"/lookup.Lookup/"))
+        {
+            Some(Scope::Lookup)
+        } else {
+            Some(Scope::Reload)
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scope, String> {
+        match s {
+            "lookup" => Ok(Scope::Lookup),
+            "reload" => Ok(Scope::Reload),
+            _ => Err(format!("Unknown scope '{}', expected lookup or reload", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Client {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+type Digested = [u8; 32];
+
+fn digest(token: &str) -> Digested {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Parses lines formatted as `name:token:scope[,scope]`, ignoring blank lines and `#` comments.
+fn parse_tokens(s: &str) -> Result<HashMap<Digested, Client>, String> {
+    let mut tokens = HashMap::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ':');
+        let (name, token, scopes) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(token), Some(scopes)) if !name.is_empty() && !token.is_empty() => (name, token, scopes),
+            _ => return Err(format!("line {}: expected 'name:token:scope[,scope]'", i + 1)),
+        };
+        let scopes = scopes
+            .split(',')
+            .map(|scope| scope.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("line {}: {}", i + 1, err))?;
+        let client = Client {
+            name: name.to_string(),
+            scopes,
+        };
+        tokens.insert(digest(token), client);
+    }
+    Ok(tokens)
+}
+
+/// The clients allowed to call the server, loaded from a token file.
+#[derive(Clone)]
+pub struct Tokens {
+    path: PathBuf,
+    clients: Arc<RwLock<HashMap<Digested, Client>>>,
+}
+
+impl Tokens {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Tokens> {
+        let tokens = Tokens {
+            path: path.as_ref().to_path_buf(),
+            clients: Arc::default(),
+        };
+        tokens.load()?;
+        Ok(tokens)
+    }
+
+    /// Reads the token file again, keeping the current tokens if it is invalid.
+    pub fn reload(&self) {
+        match self.load() {
+            Ok(n) => info!("loaded {} tokens from {}", n, self.path.display()),
+            Err(err) => error!("failed to reload {}, cause {}", self.path.display(), err),
+        }
+    }
+
+    fn load(&self) -> io::Result<usize> {
+        let clients = parse_tokens(&fs::read_to_string(&self.path)?)
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+        let n = clients.len();
+        *self.clients.write() = clients;
+        Ok(n)
+    }
+
+    /// Finds the client presenting `authorization: Bearer <token>` in `ctx`.
+    pub fn authenticate(&self, ctx: &RpcContext<'_>) -> Option<Client> {
+        let token = ctx.request_headers().iter().find_map(|(key, value)| {
+            if key.eq_ignore_ascii_case("authorization") {
+                std::str::from_utf8(value).ok()?.strip_prefix("Bearer ")
+            } else {
+                None
+            }
+        })?;
+        self.clients.read().get(&digest(token.trim())).cloned()
+    }
+}
+
+/// Rejects calls without a token granting the scope of the called method.
+#[derive(Clone)]
+pub struct AuthChecker(Tokens);
+
+impl AuthChecker {
+    pub fn new(tokens: Tokens) -> AuthChecker {
+        AuthChecker(tokens)
+    }
+}
+
+impl ServerChecker for AuthChecker {
+    fn check(&mut self, ctx: &RpcContext) -> CheckResult {
+        let scope = match Scope::required(ctx.method()) {
+            Some(scope) => scope,
+            None => return CheckResult::Continue,
+        };
+        let status = match self.0.authenticate(ctx) {
+            Some(client) if client.scopes.contains(&scope) => return CheckResult::Continue,
+            Some(client) => RpcStatus::with_message(
+                RpcStatusCode::PERMISSION_DENIED,
+                format!("'{}' is not allowed to {:?}", client.name, scope),
+            ),
+            None => RpcStatus::with_message(
+                RpcStatusCode::UNAUTHENTICATED,
+                "A valid bearer token is required".to_string(),
+            ),
+        };
+        metrics::observe_rejected(ctx, &status);
+        CheckResult::Abort(status)
+    }
+
+    fn box_clone(&self) -> Box<dyn ServerChecker> {
+        Box::new(self.clone())
+    }
+}
+
+/// Call options presenting `token` to the server, if any.
+pub fn call_option(token: Option<&str>) -> CallOption {
+    let mut opt = CallOption::default();
+    if let Some(token) = token {
+        let mut meta = MetadataBuilder::new();
+        meta.add_str("authorization", &format!("Bearer {}", token)).unwrap();
+        opt = opt.headers(meta.build());
+    }
+    opt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tokens() {
+        let tokens = parse_tokens(
+            "# clients\n\
+             batch:s3cr3t:lookup\n\
+             \n\
+             ops:t0ken:lookup, reload\n",
+        )
+        .unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(
+            tokens.get(&digest("s3cr3t")),
+            Some(&Client {
+                name: "batch".to_string(),
+                scopes: vec![Scope::Lookup]
+            })
+        );
+        assert_eq!(
+            tokens.get(&digest("t0ken")).map(|c| c.scopes.clone()),
+            Some(vec![Scope::Lookup, Scope::Reload])
+        );
+        assert_eq!(tokens.get(&digest("batch")), None);
+
+        assert!(parse_tokens("batch:s3cr3t\n").is_err());
+        assert!(parse_tokens("batch:s3cr3t:write\n").is_err());
+        assert!(parse_tokens(":s3cr3t:lookup\n").is_err());
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(Scope::required(b"/geoip2.GeoIp/Lookup"), Some(Scope::Lookup));
+        assert_eq!(Scope::required(b"/geoip2.GeoIp/Metadata"), Some(Scope::Lookup));
+        assert_eq!(Scope::required(b"/lookup.Lookup/Lookup"), Some(Scope::Lookup));
+        assert_eq!(Scope::required(b"/geoip2.GeoIp/Reload"), Some(Scope::Reload));
+        assert_eq!(Scope::required(b"/admin.Admin/Rollback"), Some(Scope::Reload));
+        assert_eq!(Scope::required(b"/grpc.health.v1.Health/Check"), None);
+        assert_eq!(Scope::required(b"/grpc.health.v1.Health/Watch"), None);
+        assert_eq!(Scope::required(b"/status.Status/Get"), Some(Scope::Reload));
+        assert_eq!(Scope::required(b"/grpc.health.v2.Health/Check"), Some(Scope::Reload));
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How `mmdb-bench` picks the address of each call.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// Any IPv4 address, most of them asked for once.
+    Random,
+    /// A pool of addresses where the `k`-th is asked for in proportion to `1 / k^s`, like a few clients making
+    /// most of the traffic.
+    Skewed(f64),
+    /// The addresses of a file, one per line, asked for evenly.
+    File(PathBuf),
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Distribution, String> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("random", None) => Ok(Distribution::Random),
+            ("skewed", None) => Ok(Distribution::Skewed(1.0)),
+            ("skewed", Some(s)) => match s.parse() {
+                Ok(s) if s > 0.0 => Ok(Distribution::Skewed(s)),
+                _ => Err(format!("The skew must be a positive number but given '{}'", s)),
+            },
+            ("file", Some(path)) if !path.is_empty() => Ok(Distribution::File(PathBuf::from(path))),
+            _ => Err(format!(
+                "Unknown distribution '{}', expected random, skewed[:s] or file:<path>",
+                s
+            )),
+        }
+    }
+}
+
+/// A xorshift generator, enough to spread the calls and the same for the same seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // The state must not be zero.
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The addresses to draw from, shared by the workers.
+pub enum Addresses {
+    Random,
+    Weighted { ips: Vec<String>, cumulative: Vec<f64> },
+}
+
+impl Addresses {
+    /// The addresses of `distribution`, with `distinct` of them in the pool of a skewed one.
+    pub fn new(distribution: &Distribution, distinct: usize, rng: &mut Rng) -> io::Result<Addresses> {
+        let (ips, weights): (Vec<String>, Vec<f64>) = match distribution {
+            Distribution::Random => return Ok(Addresses::Random),
+            Distribution::Skewed(s) => (0..distinct.max(1))
+                .map(|k| (random_ip(rng), 1.0 / ((k + 1) as f64).powf(*s)))
+                .unzip(),
+            Distribution::File(path) => fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| (line.to_string(), 1.0))
+                .unzip(),
+        };
+        if ips.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no address to look up"));
+        }
+        let cumulative = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight;
+                Some(*sum)
+            })
+            .collect();
+        Ok(Addresses::Weighted { ips, cumulative })
+    }
+
+    pub fn next(&self, rng: &mut Rng) -> String {
+        match self {
+            Addresses::Random => random_ip(rng),
+            Addresses::Weighted { ips, cumulative } => {
+                let x = rng.next_f64() * cumulative[cumulative.len() - 1];
+                let i = cumulative.partition_point(|&sum| sum <= x);
+                ips[i.min(ips.len() - 1)].clone()
+            }
+        }
+    }
+}
+
+fn random_ip(rng: &mut Rng) -> String {
+    Ipv4Addr::from(rng.next_u64() as u32).to_string()
+}
+
+/// The number of buckets per power of two, which keeps a latency within 1/64 of the bucket it is counted in.
+const SUB_BUCKETS: u64 = 64;
+
+/// Latencies counted in buckets of microseconds, exact below 128µs and log-linear above like an HDR histogram, so
+/// that a long run takes the same memory as a short one.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let i = bucket(latency.as_micros().min(u64::MAX as u128) as u64);
+        if self.counts.len() <= i {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.total += 1;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// The latency under which `p` percent of the calls were answered, rounded up to the end of its bucket.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        let i = self.counts.iter().position(|count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(Duration::from_micros(highest(i)).min(self.max))
+    }
+}
+
+/// The bucket `us` is counted in.
+fn bucket(us: u64) -> usize {
+    if us < 2 * SUB_BUCKETS {
+        return us as usize;
+    }
+    let shift = 63 - us.leading_zeros() as u64 - SUB_BUCKETS.trailing_zeros() as u64;
+    (SUB_BUCKETS * shift + (us >> shift)) as usize
+}
+
+/// The highest latency in microseconds counted in bucket `i`.
+fn highest(i: usize) -> u64 {
+    let i = i as u64;
+    if i < 2 * SUB_BUCKETS {
+        return i;
+    }
+    let shift = i / SUB_BUCKETS - 1;
+    let top = i % SUB_BUCKETS + SUB_BUCKETS;
+    ((((top + 1) as u128) << shift) - 1).min(u64::MAX as u128) as u64
+}
+
+/// The outcome of the calls made by a worker, or by all of them once merged.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The latencies of the successful calls.
+    latencies: Histogram,
+    /// The number of failed calls, by status code or transport error.
+    errors: BTreeMap<String, u64>,
+}
+
+impl Report {
+    pub fn succeeded(&mut self, latency: Duration) {
+        self.latencies.record(latency);
+    }
+
+    pub fn failed(&mut self, cause: &str) {
+        *self.errors.entry(cause.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: Report) {
+        self.latencies.merge(&other.latencies);
+        for (cause, count) in other.errors {
+            *self.errors.entry(cause).or_insert(0) += count;
+        }
+    }
+
+    pub fn calls(&self) -> u64 {
+        self.latencies.len() + self.errors.values().sum::<u64>()
+    }
+
+    /// Sums the report up, `elapsed` being how long the calls took in all.
+    pub fn summary(self, elapsed: Duration) -> Summary {
+        Summary { report: self, elapsed }
+    }
+}
+
+/// A report ready to be printed.
+pub struct Summary {
+    report: Report,
+    elapsed: Duration,
+}
+
+impl Summary {
+    /// The latency under which `p` percent of the successful calls were answered.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        self.report.latencies.percentile(p)
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let calls = self.report.calls();
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "calls:     {} in {:.2}s ({:.1}/s)",
+            calls,
+            secs,
+            if secs > 0.0 { calls as f64 / secs } else { 0.0 }
+        )?;
+        writeln!(f, "succeeded: {}", self.report.latencies.len())?;
+        if !self.report.latencies.is_empty() {
+            writeln!(f, "latency:")?;
+            for (name, p) in [
+                ("p50", 50.0),
+                ("p90", 90.0),
+                ("p99", 99.0),
+                ("p99.9", 99.9),
+                ("max", 100.0),
+            ] {
+                if let Some(latency) = self.percentile(p) {
+                    writeln!(f, "  {:<6} {:?}", name, latency)?;
+                }
+            }
+        }
+        if !self.report.errors.is_empty() {
+            writeln!(f, "errors:")?;
+            for (cause, count) in self.report.errors.iter() {
+                writeln!(f, "  {:<20} {}", cause, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_distribution_from_str() {
+        assert_eq!("random".parse(), Ok(Distribution::Random));
+        assert_eq!("skewed".parse(), Ok(Distribution::Skewed(1.0)));
+        assert_eq!("skewed:1.5".parse(), Ok(Distribution::Skewed(1.5)));
+        assert_eq!("file:ips.txt".parse(), Ok(Distribution::File(PathBuf::from("ips.txt"))));
+        assert!("skewed:0".parse::<Distribution>().is_err());
+        assert!("file".parse::<Distribution>().is_err());
+        assert!("zipf".parse::<Distribution>().is_err());
+    }
+
+    #[test]
+    fn test_skewed() {
+        let mut rng = Rng::new(42);
+        let addresses = Addresses::new(&Distribution::Skewed(1.0), 100, &mut rng).unwrap();
+        let first = match addresses {
+            Addresses::Weighted { ref ips, .. } => ips[0].clone(),
+            Addresses::Random => unreachable!(),
+        };
+        let hits = (0..10000).filter(|_| addresses.next(&mut rng) == first).count();
+        // 1 / H(100), about 19% of the calls.
+        assert!((1500..2300).contains(&hits), "{}", hits);
+    }
+
+    #[test]
+    fn test_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# clients\n203.0.113.1\n\n2001:db8::1").unwrap();
+        let distribution = Distribution::File(file.path().to_path_buf());
+        let mut rng = Rng::new(42);
+        let addresses = Addresses::new(&distribution, 0, &mut rng).unwrap();
+        for _ in 0..100 {
+            let ip = addresses.next(&mut rng);
+            assert!(ip == "203.0.113.1" || ip == "2001:db8::1", "{}", ip);
+        }
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let distribution = Distribution::File(empty.path().to_path_buf());
+        assert!(Addresses::new(&distribution, 0, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut report = Report::default();
+        for ms in (1..=100).rev() {
+            report.succeeded(Duration::from_millis(ms));
+        }
+        let mut other = Report::default();
+        other.failed("NOT_FOUND");
+        other.failed("NOT_FOUND");
+        other.failed("UNAVAILABLE");
+        report.merge(other);
+        assert_eq!(report.calls(), 103);
+
+        let summary = report.summary(Duration::from_secs(1));
+        let near = |p, ms| {
+            let latency = summary.percentile(p).unwrap();
+            let expected = Duration::from_millis(ms);
+            assert!(
+                latency >= expected && latency <= expected * 65 / 64,
+                "p{}: {:?}",
+                p,
+                latency
+            );
+        };
+        near(50.0, 50);
+        near(0.0, 1);
+        assert_eq!(summary.percentile(99.9), Some(Duration::from_millis(100)));
+        let printed = summary.to_string();
+        assert!(printed.contains("calls:     103 in 1.00s (103.0/s)"), "{}", printed);
+        assert!(printed.contains("  NOT_FOUND            2"), "{}", printed);
+
+        let empty = Report::default().summary(Duration::from_secs(1));
+        assert_eq!(empty.percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        for us in [0, 1, 127, 128, 129, 255, 256, 1000, 1_000_000, u64::MAX / 2, u64::MAX] {
+            let i = bucket(us);
+            assert!(highest(i) >= us, "{}", us);
+            assert!(highest(i) - us <= us / SUB_BUCKETS, "{}", us);
+            if i > 0 {
+                assert!(highest(i - 1) < us, "{}", us);
+            }
+        }
+        assert_eq!(bucket(127) + 1, bucket(128));
+        assert_eq!(bucket(255) + 1, bucket(256));
+    }
+}
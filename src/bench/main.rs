@@ -0,0 +1,182 @@
+mod loadgen;
+
+use crate::loadgen::{Addresses, Distribution, Report, Rng};
+use clap::Parser;
+use grpcio::{ChannelBuilder, EnvBuilder, Error};
+use log::{debug, info};
+use mmdb_grpc::proto::geoip2::*;
+use mmdb_grpc::proto::geoip2_grpc::GeoIpClient;
+use mmdb_grpc::{auth, locales, tls};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug)]
+#[clap(version, author)]
+struct Opts {
+    #[clap(short = 'H', long = "host", value_parser, default_value = "localhost")]
+    host: String,
+    #[clap(short = 'P', long = "port", value_parser, default_value = "50000")]
+    port: u16,
+    #[clap(long = "tls", value_parser)]
+    tls: bool,
+    #[clap(long = "tls-ca", value_parser)]
+    tls_ca: Option<String>,
+    #[clap(long = "tls-cert", value_parser, requires = "tls_key")]
+    tls_cert: Option<String>,
+    #[clap(long = "tls-key", value_parser, requires = "tls_cert")]
+    tls_key: Option<String>,
+    #[clap(long = "tls-server-name", value_parser)]
+    tls_server_name: Option<String>,
+    #[clap(long = "token", value_parser)]
+    token: Option<String>,
+    #[clap(short = 'c', long = "concurrency", value_parser, default_value = "16")]
+    concurrency: usize,
+    #[clap(long = "rps", value_parser)]
+    rps: Option<f64>,
+    #[clap(short = 'd', long = "duration", value_parser = duration, default_value = "10s")]
+    duration: Duration,
+    #[clap(short = 'n', long = "requests", value_parser)]
+    requests: Option<u64>,
+    #[clap(long = "timeout", value_parser = duration, default_value = "1s")]
+    timeout: Duration,
+    #[clap(long = "ips", value_parser, default_value = "random")]
+    ips: Distribution,
+    #[clap(long = "distinct", value_parser, default_value = "10000")]
+    distinct: usize,
+    #[clap(long = "locales", value_parser = locales::parse, value_delimiter = ',')]
+    locales: Vec<Message_Locale>,
+    #[clap(long = "seed", value_parser)]
+    seed: Option<u64>,
+}
+
+impl Opts {
+    fn host(&self) -> &String {
+        &self.host
+    }
+    fn tls(&self) -> bool {
+        self.tls || self.tls_ca.is_some() || self.tls_cert.is_some()
+    }
+    /// The delay between the calls of a worker to keep to `--rps` in all, at most `--duration`.
+    fn interval(&self) -> Option<Duration> {
+        self.rps.map(|rps| {
+            Duration::try_from_secs_f64(self.concurrency as f64 / rps).map_or(self.duration, |i| i.min(self.duration))
+        })
+    }
+}
+
+fn duration(s: &str) -> Result<Duration, String> {
+    parse_duration::parse(s).map_err(|_| format!("The duration must be like 30s or 1h but given '{}'", s))
+}
+
+/// The name a failed call is counted under, its status code when it got one.
+fn cause(err: &Error) -> String {
+    match err {
+        // A code prints as `5-NOT_FOUND`.
+        Error::RpcFailure(status) => {
+            let code = status.code().to_string();
+            code.split_once('-').map_or(code.clone(), |(_, name)| name.to_string())
+        }
+        err => err.to_string(),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let opts = Arc::new(Opts::parse());
+    if opts.concurrency == 0 || opts.rps.map_or(false, |rps| !rps.is_finite() || rps <= 0.0) {
+        eprintln!("--concurrency and --rps must be positive");
+        std::process::exit(2);
+    }
+
+    let seed = opts.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    debug!("-> seed: {}", seed);
+    let addresses = match Addresses::new(&opts.ips, opts.distinct, &mut Rng::new(seed)) {
+        Ok(addresses) => Arc::new(addresses),
+        Err(err) => {
+            eprintln!("failed to read the addresses: {}", err);
+            std::process::exit(66);
+        }
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let addr = format!("{}:{}", opts.host(), opts.port);
+    let mut builder = ChannelBuilder::new(env);
+    if let Some(ref name) = opts.tls_server_name {
+        builder = builder.override_ssl_target(name.as_str());
+    }
+    let ch = if opts.tls() {
+        match tls::channel_credentials(opts.tls_ca.as_ref(), opts.tls_cert.as_ref(), opts.tls_key.as_ref()) {
+            Ok(creds) => builder.secure_connect(addr.as_str(), creds),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(66);
+            }
+        }
+    } else {
+        builder.connect(addr.as_str())
+    };
+    let client = GeoIpClient::new(ch);
+
+    info!(
+        "sending lookups to {} from {} workers for {:?}",
+        addr, opts.concurrency, opts.duration
+    );
+    let issued = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+    let deadline = started + opts.duration;
+    let workers: Vec<_> = (0..opts.concurrency)
+        .map(|worker| {
+            let (opts, addresses, client, issued) = (opts.clone(), addresses.clone(), client.clone(), issued.clone());
+            thread::spawn(move || {
+                let mut rng = Rng::new(seed.wrapping_add(worker as u64 + 1));
+                let mut report = Report::default();
+                let interval = opts.interval();
+                // Spread the first calls of the workers over an interval.
+                let mut next =
+                    started + interval.map_or(Duration::ZERO, |i| i * worker as u32 / opts.concurrency as u32);
+                loop {
+                    // Under --rps a call is timed from when it was due rather than when it was sent, so the wait
+                    // behind a slow call counts in the latency instead of being left out (coordinated omission).
+                    let due = interval.map(|interval| {
+                        let due = next;
+                        let now = Instant::now();
+                        if due > now {
+                            thread::sleep(due - now);
+                        }
+                        next += interval;
+                        due
+                    });
+                    let budget = opts
+                        .requests
+                        .map_or(true, |n| issued.fetch_add(1, Ordering::Relaxed) < n);
+                    if !budget || Instant::now() >= deadline {
+                        break;
+                    }
+
+                    let mut msg = Message::default();
+                    msg.set_ip(addresses.next(&mut rng));
+                    msg.set_locales(opts.locales.clone());
+                    let call = due.unwrap_or_else(Instant::now);
+                    match client.lookup_opt(&msg, auth::call_option(opts.token.as_deref()).timeout(opts.timeout)) {
+                        Ok(_) => report.succeeded(call.elapsed()),
+                        Err(err) => report.failed(&cause(&err)),
+                    }
+                }
+                report
+            })
+        })
+        .collect();
+
+    let mut report = Report::default();
+    for worker in workers {
+        report.merge(worker.join().unwrap());
+    }
+    print!("{}", report.summary(started.elapsed()));
+}
@@ -0,0 +1,169 @@
+use crate::locales::Locales;
+use crate::metrics;
+use crate::proto::geoip2::CityReply;
+use lru::LruCache;
+use maxminddb::MaxMindDBError;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+/// The number of locks the entries are spread over, so that concurrent lookups rarely wait on each other.
+const SHARDS: usize = 16;
+
+/// The record an address was found in, along with the requested locales.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    record: usize,
+    locales: Locales,
+}
+
+struct Entries {
+    generation: u64,
+    replies: LruCache<Key, Arc<CityReply>>,
+}
+
+/// The most recently used replies, each shared by the networks whose addresses are found in the same
+/// record of the database.
+///
+/// The entries belong to a generation of the database and are dropped as soon as a lookup is made in
+/// another one, so that no reply outlives a reload. They are split by key into shards holding an even
+/// share of the capacity, each evicting its least recently used entries on its own.
+#[derive(Clone)]
+pub struct ReplyCache {
+    shards: Arc<[Mutex<Entries>]>,
+    hasher: RandomState,
+}
+
+impl ReplyCache {
+    pub fn new(capacity: NonZeroUsize) -> ReplyCache {
+        let shards = SHARDS.min(capacity.get());
+        let per_shard = NonZeroUsize::new(capacity.get().div_ceil(shards)).unwrap();
+        ReplyCache {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Entries {
+                        generation: 0,
+                        replies: LruCache::new(per_shard),
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The reply for `record` in `locales`, converted by `convert` only the first time it is asked for since
+    /// the database of `generation` is served.
+    pub(crate) fn get_or_insert_with<F>(
+        &self,
+        generation: u64,
+        record: usize,
+        locales: Locales,
+        convert: F,
+    ) -> Result<Arc<CityReply>, MaxMindDBError>
+    where
+        F: FnOnce() -> Result<CityReply, MaxMindDBError>,
+    {
+        let key = Key { record, locales };
+        if let Some(reply) = self.get(generation, &key) {
+            metrics::observe_cache(true);
+            return Ok(reply);
+        }
+        metrics::observe_cache(false);
+
+        let reply = Arc::new(convert()?);
+        self.insert(generation, key, reply.clone());
+        Ok(reply)
+    }
+
+    fn shard(&self, key: &Key) -> &Mutex<Entries> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    fn get(&self, generation: u64, key: &Key) -> Option<Arc<CityReply>> {
+        let mut entries = self.shard(key).lock().unwrap();
+        if entries.generation != generation {
+            return None;
+        }
+        entries.replies.get(key).cloned()
+    }
+
+    fn insert(&self, generation: u64, key: Key, reply: Arc<CityReply>) {
+        let mut entries = self.shard(&key).lock().unwrap();
+        if entries.generation != generation {
+            entries.replies.clear();
+            entries.generation = generation;
+        }
+        entries.replies.put(key, reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn len(cache: &ReplyCache) -> usize {
+        cache
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().replies.len())
+            .sum()
+    }
+
+    #[test]
+    fn test_generation() {
+        // A single shard, so that the other key is in the same one.
+        let cache = ReplyCache::new(NonZeroUsize::new(1).unwrap());
+        let key = Key {
+            record: 0,
+            locales: Locales::all(),
+        };
+        cache.insert(1, key.clone(), Arc::default());
+        assert_eq!(cache.get(1, &key), Some(Arc::default()));
+        assert_eq!(cache.get(2, &key), None);
+
+        cache.insert(
+            2,
+            Key {
+                record: 42,
+                locales: Locales::all(),
+            },
+            Arc::default(),
+        );
+        assert_eq!(len(&cache), 1);
+        assert_eq!(cache.get(2, &key), None);
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let cache = ReplyCache::new(NonZeroUsize::new(2).unwrap());
+        let mut reply = CityReply::default();
+        reply.mut_country().set_iso_code("JP".to_string());
+
+        let converted = cache
+            .get_or_insert_with(1, 0, Locales::all(), || Ok(reply.clone()))
+            .unwrap();
+        assert_eq!(*converted, reply);
+        let cached = cache
+            .get_or_insert_with(1, 0, Locales::all(), || panic!("converted twice"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&converted, &cached));
+
+        let failed = cache.get_or_insert_with(1, 1, Locales::all(), || {
+            Err(MaxMindDBError::DecodingError("invalid record".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(len(&cache), 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = ReplyCache::new(NonZeroUsize::new(100).unwrap());
+        assert_eq!(cache.shards.len(), SHARDS);
+        for record in 0..1000 {
+            let _ = cache.get_or_insert_with(1, record, Locales::all(), || Ok(CityReply::default()));
+        }
+        // Each shard holds 7 entries, its share of the capacity rounded up.
+        assert_eq!(len(&cache), SHARDS * 7);
+    }
+}
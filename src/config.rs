@@ -0,0 +1,299 @@
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+/// The `--config` option, which must be defined by every parser given to [`parse`].
+pub const CONFIG_ID: &str = "config";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Cli(clap::Error),
+    Io(String, io::Error),
+    Syntax(String, String),
+    Invalid(String, String),
+}
+
+impl ConfigError {
+    /// EX_CONFIG from sysexits.h for a bad value, wherever it was given, like the other configuration errors
+    /// of mmdb-server. Misspelled or missing options keep clap's code, as do `--help` and `--version`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ConfigError::Cli(err) => match err.kind() {
+                ErrorKind::InvalidValue
+                | ErrorKind::ValueValidation
+                | ErrorKind::InvalidUtf8
+                | ErrorKind::NoEquals
+                | ErrorKind::TooManyValues
+                | ErrorKind::TooFewValues
+                | ErrorKind::WrongNumberOfValues => 78,
+                _ => err.exit_code(),
+            },
+            _ => 78,
+        }
+    }
+
+    /// Prints the error and exits, the way clap does for command line errors.
+    pub fn exit(&self) -> ! {
+        match self {
+            ConfigError::Cli(err) => {
+                let _ = err.print();
+            }
+            err => eprintln!("error: {}", err),
+        }
+        process::exit(self.exit_code())
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Cli(err) => err.fmt(f),
+            ConfigError::Io(path, err) => write!(f, "failed to read {}, cause {}", path, err),
+            ConfigError::Syntax(path, err) => write!(f, "failed to parse {}, cause {}", path, err),
+            ConfigError::Invalid(path, msg) => write!(f, "invalid {}: {}", path, msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn scalar(&self) -> Option<String> {
+        match self {
+            Value::Bool(v) => Some(v.to_string()),
+            Value::Int(v) => Some(v.to_string()),
+            Value::Float(v) => Some(v.to_string()),
+            Value::String(v) => Some(v.clone()),
+            Value::List(_) => None,
+        }
+    }
+}
+
+/// Reads a TOML file, or a YAML one when named `*.yaml` or `*.yml`, keyed by long option names.
+fn read(path: &str) -> Result<BTreeMap<String, Value>, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_string(), err))?;
+    let syntax = |err: &dyn Display| ConfigError::Syntax(path.to_string(), err.to_string());
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|err| syntax(&err)),
+        _ => toml::from_str(&content).map_err(|err| syntax(&err)),
+    }
+}
+
+/// Turns the file values into command line arguments, skipping options given on the command line or
+/// in the environment so that those take precedence.
+fn file_args(
+    path: &str,
+    values: BTreeMap<String, Value>,
+    command: &clap::Command,
+    matches: &clap::ArgMatches,
+) -> Result<Vec<OsString>, ConfigError> {
+    let invalid = |msg: String| ConfigError::Invalid(path.to_string(), msg);
+    let mut args = Vec::new();
+    for (key, value) in values {
+        let name = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()) && arg.get_id().as_str() != CONFIG_ID)
+            .ok_or_else(|| invalid(format!("unknown option '{}'", key)))?;
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            Value::List(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = value
+                .scalar()
+                .ok_or_else(|| invalid(format!("'{}' must not be a nested list", key)))?;
+            if matches!(arg.get_action(), ArgAction::SetTrue) {
+                match value.as_str() {
+                    "true" => args.push(OsString::from(format!("--{}", name))),
+                    "false" => {}
+                    _ => return Err(invalid(format!("'{}' must be true or false", key))),
+                }
+            } else {
+                args.push(OsString::from(format!("--{}={}", name, value)));
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Parses `P` from the command line, the environment and the file given by `--config`, in that order
+/// of precedence.
+pub fn parse<P, I, A>(args: I) -> Result<P, ConfigError>
+where
+    P: CommandFactory + FromArgMatches,
+    I: IntoIterator<Item = A>,
+    A: Into<OsString>,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let command = P::command();
+    let matches = command.clone().try_get_matches_from(&args).map_err(ConfigError::Cli)?;
+    let path = match matches.get_one::<String>(CONFIG_ID) {
+        Some(path) => path.clone(),
+        None => return P::from_arg_matches(&matches).map_err(ConfigError::Cli),
+    };
+
+    let mut merged = args[..1].to_vec();
+    merged.extend(file_args(&path, read(&path)?, &command, &matches)?);
+    merged.extend(args[1..].iter().cloned());
+    let matches = command.try_get_matches_from(merged).map_err(ConfigError::Cli)?;
+    P::from_arg_matches(&matches).map_err(ConfigError::Cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[derive(Parser, Debug, PartialEq)]
+    struct Opts {
+        #[clap(long = "config", value_parser)]
+        config: Option<String>,
+        #[clap(short = 'H', long = "host", value_parser, default_value = "localhost")]
+        host: String,
+        #[clap(short = 'P', long = "port", value_parser, default_value = "50000")]
+        port: u16,
+        #[clap(
+            short = 'W',
+            long = "workers",
+            value_parser,
+            env = "MMDB_CONFIG_TEST_WORKERS",
+            default_value = "1"
+        )]
+        workers: usize,
+        #[clap(long = "listen", value_parser)]
+        listen: Vec<String>,
+        #[clap(long = "geoip-reload", value_parser)]
+        geoip_reload: bool,
+        #[clap(long = "interval", value_parser = duration)]
+        interval: Option<Duration>,
+    }
+
+    fn duration(s: &str) -> Result<Duration, String> {
+        parse_duration::parse(s).map_err(|_| format!("The duration must be like 30s or 1h but given '{}'", s))
+    }
+
+    fn config(suffix: &str, content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let file = config(
+            ".toml",
+            "host = \"0.0.0.0\"\nport = 50001\nlisten = [\"[::]:50000\", \"unix:///run/mmdb.sock\"]\ngeoip-reload = true\n",
+        );
+        let path = file.path().to_str().unwrap();
+
+        let opts: Opts = parse(["mmdb-server", "--config", path]).unwrap();
+        assert_eq!(opts.host, "0.0.0.0");
+        assert_eq!(opts.port, 50001);
+        assert_eq!(opts.listen, vec!["[::]:50000", "unix:///run/mmdb.sock"]);
+        assert!(opts.geoip_reload);
+
+        let opts: Opts = parse([
+            "mmdb-server",
+            "--config",
+            path,
+            "-H",
+            "127.0.0.1",
+            "--listen",
+            "[::]:50002",
+        ])
+        .unwrap();
+        assert_eq!(opts.host, "127.0.0.1");
+        assert_eq!(opts.port, 50001);
+        assert_eq!(opts.listen, vec!["[::]:50002"]);
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let file = config(".yaml", "host: 0.0.0.0\ngeoip_reload: false\n");
+        let opts: Opts = parse(["mmdb-server", "--config", file.path().to_str().unwrap()]).unwrap();
+        assert_eq!(opts.host, "0.0.0.0");
+        assert!(!opts.geoip_reload);
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        // Setting a variable races the other tests reading the environment, so the test runs itself again
+        // in a child process given the variable.
+        if std::env::var_os("MMDB_CONFIG_TEST_WORKERS").is_none() {
+            let name = concat!(module_path!(), "::test_env_overrides_file");
+            let output = process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", name.split_once("::").unwrap().1])
+                .env("MMDB_CONFIG_TEST_WORKERS", "4")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success() && stdout.contains("1 passed"), "{}", stdout);
+            return;
+        }
+
+        let file = config(".toml", "workers = 2\n");
+        let opts: Opts = parse(["mmdb-server", "--config", file.path().to_str().unwrap()]).unwrap();
+        assert_eq!(opts.workers, 4);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let file = config(".toml", "hots = \"0.0.0.0\"\n");
+        let result: Result<Opts, _> = parse(["mmdb-server", "--config", file.path().to_str().unwrap()]);
+        assert!(matches!(result, Err(ConfigError::Invalid(_, _))));
+
+        let file = config(".toml", "host = [\n");
+        let result: Result<Opts, _> = parse(["mmdb-server", "--config", file.path().to_str().unwrap()]);
+        assert!(matches!(result, Err(ConfigError::Syntax(_, _))));
+
+        let result: Result<Opts, _> = parse(["mmdb-server", "--config", "/nonexistent/mmdb.toml"]);
+        assert!(matches!(result, Err(ConfigError::Io(_, _))));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let exit_code = |args: &[&str]| {
+            parse::<Opts, _, _>([&["mmdb-server"], args].concat())
+                .unwrap_err()
+                .exit_code()
+        };
+        assert_eq!(exit_code(&["--port", "http"]), 78);
+        assert_eq!(exit_code(&["--interval", "soon"]), 78);
+        assert_eq!(exit_code(&["--hots", "0.0.0.0"]), 2);
+        assert_eq!(exit_code(&["--help"]), 0);
+
+        let file = config(".toml", "interval = \"soon\"\n");
+        assert_eq!(exit_code(&["--config", file.path().to_str().unwrap()]), 78);
+        let file = config(".toml", "hots = \"0.0.0.0\"\n");
+        assert_eq!(exit_code(&["--config", file.path().to_str().unwrap()]), 78);
+        assert_eq!(exit_code(&["--config", "/nonexistent/mmdb.toml"]), 78);
+    }
+}
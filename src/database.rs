@@ -0,0 +1,273 @@
+use crate::tree::SearchTree;
+use maxminddb::{self, MaxMindDBError};
+use std::collections::VecDeque;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A loaded database along with where and when it was loaded from.
+pub struct Version<T>
+where
+    T: AsRef<[u8]>,
+{
+    path: String,
+    reader: maxminddb::Reader<T>,
+    /// The data the reader was made from along with its search tree, when the version is made from the data.
+    tree: Option<(T, SearchTree)>,
+    loaded_at: SystemTime,
+    load_duration: Duration,
+}
+
+impl Version<Arc<[u8]>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Version<Arc<[u8]>>, MaxMindDBError> {
+        let started = Instant::now();
+        let data = fs::read(path.as_ref())?;
+        let mut version = Version::from_source(path.as_ref().display().to_string(), Arc::from(data))?;
+        version.load_duration = started.elapsed();
+        Ok(version)
+    }
+}
+
+impl<T> Version<T>
+where
+    T: AsRef<[u8]>,
+{
+    /// A version of which lookups always decode their record, as the reader does not give its data back to
+    /// walk the search tree with. Prefer [`Version::from_source`] to have replies cached.
+    pub fn new(path: String, reader: maxminddb::Reader<T>) -> Version<T> {
+        Version {
+            path,
+            reader,
+            tree: None,
+            loaded_at: SystemTime::now(),
+            load_duration: Duration::default(),
+        }
+    }
+
+    /// Reads the database in `data`, which is kept to walk the search tree without decoding records. `T` is
+    /// best cheap to clone, such as `Arc<[u8]>`, as the reader holds a clone.
+    pub fn from_source(path: String, data: T) -> Result<Version<T>, MaxMindDBError>
+    where
+        T: Clone,
+    {
+        let reader = maxminddb::Reader::from_source(data.clone())?;
+        let tree = SearchTree::new(data.as_ref(), &reader.metadata);
+        Ok(Version {
+            tree: Some((data, tree)),
+            ..Version::new(path, reader)
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn reader(&self) -> &maxminddb::Reader<T> {
+        &self.reader
+    }
+
+    /// The offset of the record `ip` is found in, the same for every network sharing the record.
+    pub(crate) fn record(&self, ip: IpAddr) -> Option<usize> {
+        let (data, tree) = self.tree.as_ref()?;
+        tree.record(data.as_ref(), ip)
+    }
+
+    pub fn loaded_at(&self) -> SystemTime {
+        self.loaded_at
+    }
+
+    pub fn load_duration(&self) -> Duration {
+        self.load_duration
+    }
+}
+
+/// Outcomes of the reloads attempted since the server started.
+#[derive(Clone, Debug, Default)]
+pub struct Reloads {
+    pub last_attempt: Option<SystemTime>,
+    pub last_error: Option<(SystemTime, String)>,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// The currently served database and the ones it replaced, most recent first.
+///
+/// There is no current database when the server started before the file was in place.
+pub struct Database<T>
+where
+    T: AsRef<[u8]>,
+{
+    current: Option<Version<T>>,
+    previous: VecDeque<Version<T>>,
+    keep: usize,
+    reloads: Reloads,
+    generation: u64,
+}
+
+impl<T> Database<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub fn new(version: Version<T>, keep: usize) -> Database<T> {
+        let mut db = Database::empty(keep);
+        db.current = Some(version);
+        db
+    }
+
+    /// A database to be loaded later.
+    pub fn empty(keep: usize) -> Database<T> {
+        Database {
+            current: None,
+            previous: VecDeque::with_capacity(keep),
+            keep,
+            reloads: Reloads::default(),
+            generation: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Version<T>> {
+        self.current.as_ref()
+    }
+
+    pub fn reader(&self) -> Option<&maxminddb::Reader<T>> {
+        self.current.as_ref().map(|version| &version.reader)
+    }
+
+    pub fn previous(&self) -> impl Iterator<Item = &Version<T>> {
+        self.previous.iter()
+    }
+
+    pub fn reloads(&self) -> &Reloads {
+        &self.reloads
+    }
+
+    /// Changes whenever another version is served, telling what was derived from the previous one is stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Serves `version` from now on, keeping the replaced one for a later rollback.
+    pub fn replace(&mut self, version: Version<T>) -> &Version<T> {
+        if let Some(replaced) = self.current.take() {
+            self.retain(replaced);
+        }
+        self.generation += 1;
+        self.current.insert(version)
+    }
+
+    /// Records the outcome of a reload, serving the loaded version if it succeeded.
+    pub fn reload(&mut self, loaded: Result<Version<T>, MaxMindDBError>) -> Result<&Version<T>, MaxMindDBError> {
+        let now = SystemTime::now();
+        self.reloads.last_attempt = Some(now);
+        match loaded {
+            Ok(version) => {
+                self.reloads.succeeded += 1;
+                Ok(self.replace(version))
+            }
+            Err(err) => {
+                self.reloads.failed += 1;
+                self.reloads.last_error = Some((now, err.to_string()));
+                Err(err)
+            }
+        }
+    }
+
+    /// Serves the most recent previous version built at `build_epoch` again.
+    pub fn rollback(&mut self, build_epoch: u64) -> Option<&Version<T>> {
+        let i = self
+            .previous
+            .iter()
+            .position(|v| v.reader.metadata.build_epoch == build_epoch)?;
+        let version = self.previous.remove(i)?;
+        Some(self.replace(version))
+    }
+
+    fn retain(&mut self, version: Version<T>) {
+        if self.keep == 0 {
+            return;
+        }
+        self.previous.truncate(self.keep - 1);
+        self.previous.push_front(version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    fn version(build_epoch: u64) -> Version<Vec<u8>> {
+        Version::from_source(format!("{}.mmdb", build_epoch), fixture::database(build_epoch)).unwrap()
+    }
+
+    fn epochs(db: &Database<Vec<u8>>) -> (Option<u64>, Vec<u64>) {
+        let epoch = |v: &Version<Vec<u8>>| v.reader().metadata.build_epoch;
+        (db.current().map(epoch), db.previous().map(epoch).collect())
+    }
+
+    #[test]
+    fn test_record() {
+        let ip = "10.0.0.1".parse().unwrap();
+        assert!(version(1).record(ip).is_some());
+
+        let reader = maxminddb::Reader::from_source(fixture::database(1)).unwrap();
+        assert_eq!(Version::new("1.mmdb".to_string(), reader).record(ip), None);
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut db = Database::new(version(1), 2);
+        for build_epoch in 2..=4 {
+            db.reload(Ok(version(build_epoch))).unwrap();
+        }
+        assert_eq!(epochs(&db), (Some(4), vec![3, 2]));
+        assert_eq!(db.generation(), 3);
+        assert_eq!(db.reloads().succeeded, 3);
+
+        let mut db = Database::new(version(1), 0);
+        db.reload(Ok(version(2))).unwrap();
+        assert_eq!(epochs(&db), (Some(2), vec![]));
+    }
+
+    #[test]
+    fn test_reload_failure() {
+        let mut db = Database::new(version(1), 1);
+        let failed = db.reload(Err(MaxMindDBError::InvalidDatabaseError("truncated".to_string())));
+        assert!(failed.is_err());
+        assert_eq!(epochs(&db), (Some(1), vec![]));
+        assert_eq!(db.generation(), 0);
+        assert_eq!(db.reloads().failed, 1);
+        assert!(db.reloads().last_error.is_some());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut db = Database::new(version(1), 2);
+        db.reload(Ok(version(2))).unwrap();
+        db.reload(Ok(version(3))).unwrap();
+
+        let served = db.rollback(1).map(|v| v.reader().metadata.build_epoch);
+        assert_eq!(served, Some(1));
+        assert_eq!(epochs(&db), (Some(1), vec![3, 2]));
+        assert_eq!(db.generation(), 3);
+
+        assert!(db.rollback(42).is_none());
+        assert_eq!(epochs(&db), (Some(1), vec![3, 2]));
+
+        let mut db = Database::new(version(1), 0);
+        db.reload(Ok(version(2))).unwrap();
+        assert!(db.rollback(1).is_none());
+        assert_eq!(epochs(&db), (Some(2), vec![]));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut db = Database::<Vec<u8>>::empty(1);
+        assert!(db.reader().is_none());
+        assert!(db.rollback(1).is_none());
+        db.reload(Ok(version(1))).unwrap();
+        assert_eq!(epochs(&db), (Some(1), vec![]));
+    }
+}
@@ -0,0 +1,208 @@
+//! A city database generated for the tests and the benchmarks, so that they run without a GeoLite2 download.
+//!
+//! Every /12 IPv4 network is mapped to one of `RECORDS` cities, each named in all the locales of the
+//! GeoIP2 databases. The benchmarks include this file with `#[path]`, as they cannot see test modules.
+
+// Each test and benchmark only uses a part of it.
+#![allow(dead_code)]
+
+use std::net::Ipv4Addr;
+
+/// The prefix length of the networks in the search tree.
+const DEPTH: u32 = 12;
+pub const RECORDS: usize = 256;
+
+const LOCALES: [&str; 8] = ["de", "en", "es", "fr", "ja", "pt-BR", "ru", "zh-CN"];
+
+enum Value {
+    Map(Vec<(&'static str, Value)>),
+    String(String),
+    Double(f64),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Array(Vec<Value>),
+    Bool(bool),
+}
+
+impl Value {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Map(entries) => {
+                control(buf, 7, entries.len());
+                for (key, value) in entries {
+                    Value::String(key.to_string()).encode(buf);
+                    value.encode(buf);
+                }
+            }
+            Value::String(s) => {
+                control(buf, 2, s.len());
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Value::Double(d) => {
+                control(buf, 3, 8);
+                buf.extend_from_slice(&d.to_be_bytes());
+            }
+            Value::U16(n) => unsigned(buf, 5, *n as u64),
+            Value::U32(n) => unsigned(buf, 6, *n as u64),
+            Value::U64(n) => unsigned(buf, 9, *n),
+            Value::Array(values) => {
+                control(buf, 11, values.len());
+                for value in values {
+                    value.encode(buf);
+                }
+            }
+            Value::Bool(b) => control(buf, 14, *b as usize),
+        }
+    }
+}
+
+/// Writes the control byte of a field of `kind` holding `size` bytes or entries.
+fn control(buf: &mut Vec<u8>, kind: u8, size: usize) {
+    let (bits, extra) = match size {
+        0..=28 => (size as u8, Vec::new()),
+        29..=284 => (29, vec![(size - 29) as u8]),
+        285..=65820 => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+        _ => (31, ((size - 65821) as u32).to_be_bytes()[1..].to_vec()),
+    };
+    if kind <= 7 {
+        buf.push(kind << 5 | bits);
+    } else {
+        buf.push(bits);
+        buf.push(kind - 7);
+    }
+    buf.extend_from_slice(&extra);
+}
+
+fn unsigned(buf: &mut Vec<u8>, kind: u8, n: u64) {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    control(buf, kind, bytes.len() - skip);
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn names(name: &str) -> Value {
+    Value::Map(
+        LOCALES
+            .iter()
+            .map(|&locale| (locale, Value::String(format!("{} ({})", name, locale))))
+            .collect(),
+    )
+}
+
+fn country(i: usize) -> Value {
+    Value::Map(vec![
+        ("geoname_id", Value::U32(1_000_000 + (i / 16) as u32)),
+        ("is_in_european_union", Value::Bool(i % 3 == 0)),
+        ("iso_code", Value::String(format!("C{:X}", i / 16))),
+        ("names", names(&format!("Country {}", i / 16))),
+    ])
+}
+
+fn city(i: usize) -> Value {
+    Value::Map(vec![
+        (
+            "city",
+            Value::Map(vec![
+                ("geoname_id", Value::U32(2_000_000 + i as u32)),
+                ("names", names(&format!("City {}", i))),
+            ]),
+        ),
+        (
+            "continent",
+            Value::Map(vec![
+                ("code", Value::String(format!("K{}", i % 7))),
+                ("geoname_id", Value::U32(6_255_000 + (i % 7) as u32)),
+                ("names", names(&format!("Continent {}", i % 7))),
+            ]),
+        ),
+        ("country", country(i)),
+        (
+            "location",
+            Value::Map(vec![
+                ("accuracy_radius", Value::U16(100)),
+                ("latitude", Value::Double(i as f64 * 0.5 - 64.0)),
+                ("longitude", Value::Double(i as f64 - 128.0)),
+                ("time_zone", Value::String("Asia/Tokyo".to_string())),
+            ]),
+        ),
+        ("postal", Value::Map(vec![("code", Value::String(format!("{:05}", i)))])),
+        ("registered_country", country(i)),
+        (
+            "subdivisions",
+            Value::Array(vec![Value::Map(vec![
+                ("geoname_id", Value::U32(3_000_000 + i as u32)),
+                ("iso_code", Value::String(format!("{:02}", i % 50))),
+                ("names", names(&format!("Subdivision {}", i))),
+            ])]),
+        ),
+    ])
+}
+
+fn metadata(node_count: u32, build_epoch: u64) -> Value {
+    Value::Map(vec![
+        ("binary_format_major_version", Value::U16(2)),
+        ("binary_format_minor_version", Value::U16(0)),
+        ("build_epoch", Value::U64(build_epoch)),
+        ("database_type", Value::String("Bench-City".to_string())),
+        (
+            "description",
+            Value::Map(vec![(
+                "en",
+                Value::String("A generated database for benchmarks".to_string()),
+            )]),
+        ),
+        ("ip_version", Value::U16(4)),
+        (
+            "languages",
+            Value::Array(LOCALES.iter().map(|l| Value::String(l.to_string())).collect()),
+        ),
+        ("node_count", Value::U32(node_count)),
+        ("record_size", Value::U16(24)),
+    ])
+}
+
+/// The database, built at `build_epoch` so that reloads can be told apart.
+pub fn database(build_epoch: u64) -> Vec<u8> {
+    let mut data = Vec::new();
+    let offsets: Vec<usize> = (0..RECORDS)
+        .map(|i| {
+            let offset = data.len();
+            city(i).encode(&mut data);
+            offset
+        })
+        .collect();
+
+    // A complete tree, where node `n` has the nodes `2n + 1` and `2n + 2` below it and the nodes past the
+    // last one stand for the networks.
+    let node_count = (1 << DEPTH) - 1;
+    let mut db = Vec::with_capacity(node_count * 6 + 16 + data.len());
+    for node in 0..node_count {
+        for child in [2 * node + 1, 2 * node + 2] {
+            let record = if child < node_count {
+                child
+            } else {
+                node_count + 16 + offsets[(child - node_count) % RECORDS]
+            };
+            db.extend_from_slice(&(record as u32).to_be_bytes()[1..]);
+        }
+    }
+    db.extend_from_slice(&[0; 16]);
+    db.extend_from_slice(&data);
+    db.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+    metadata(node_count as u32, build_epoch).encode(&mut db);
+    db
+}
+
+/// `n` addresses spread over every network of the database, always the same ones.
+pub fn addresses(n: usize) -> Vec<String> {
+    let mut state: u32 = 0x9e37_79b9;
+    (0..n)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Ipv4Addr::from(state).to_string()
+        })
+        .collect()
+}
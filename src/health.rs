@@ -0,0 +1,117 @@
+use crate::database::Database;
+use grpcio_health::proto::ServingStatus;
+use grpcio_health::HealthService;
+use log::{info, warn};
+use spin::RwLock;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const GEOIP_SERVICE: &str = "geoip2.GeoIp";
+pub const LOOKUP_SERVICE: &str = "lookup.Lookup";
+pub const ADMIN_SERVICE: &str = "admin.Admin";
+
+/// Derives the serving status of each service from the loaded database.
+///
+/// The statuses are held by a [`HealthService`], which answers `Check` and pushes
+/// every change to `Watch` streams.
+pub struct HealthReporter<T>
+where
+    T: AsRef<[u8]>,
+{
+    db: Arc<RwLock<Database<T>>>,
+    service: HealthService,
+    max_age: Option<Duration>,
+    serving: Option<bool>,
+}
+
+impl<T> HealthReporter<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub fn new(db: Arc<RwLock<Database<T>>>, max_age: Option<Duration>) -> HealthReporter<T> {
+        let service = HealthService::default();
+        for name in ["", GEOIP_SERVICE, LOOKUP_SERVICE, ADMIN_SERVICE] {
+            service.set_serving_status(name, ServingStatus::NotServing);
+        }
+        HealthReporter {
+            db,
+            service,
+            max_age,
+            serving: None,
+        }
+    }
+
+    pub fn service(&self) -> HealthService {
+        self.service.clone()
+    }
+
+    /// Changes the allowed database age, taking effect on the next refresh.
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        if self.max_age != max_age {
+            self.max_age = max_age;
+            self.serving = None;
+        }
+    }
+
+    /// Re-evaluates the database age, updating the statuses only when they change.
+    ///
+    /// The lookup service is not serving until a database is loaded.
+    pub fn refresh(&mut self) {
+        let build_epoch = self.db.read().reader().map(|reader| reader.metadata.build_epoch);
+        let serving = build_epoch.is_some_and(|build_epoch| {
+            self.max_age
+                .is_none_or(|max_age| is_fresh(build_epoch, max_age, SystemTime::now()))
+        });
+        if self.serving == Some(serving) {
+            return;
+        }
+
+        let status = match build_epoch {
+            Some(build_epoch) if serving => {
+                info!("serving the database built at {}", build_epoch);
+                ServingStatus::Serving
+            }
+            Some(build_epoch) => {
+                warn!("the database built at {} is older than the allowed age", build_epoch);
+                ServingStatus::NotServing
+            }
+            None => {
+                warn!("no database is loaded yet");
+                ServingStatus::NotServing
+            }
+        };
+        self.service.set_serving_status("", status);
+        self.service.set_serving_status(GEOIP_SERVICE, status);
+        self.service.set_serving_status(LOOKUP_SERVICE, status);
+        self.service.set_serving_status(ADMIN_SERVICE, ServingStatus::Serving);
+        self.serving = Some(serving);
+    }
+
+    /// Reports every service as not serving from now on, regardless of the database.
+    pub fn shutdown(&mut self) {
+        info!("stop serving");
+        self.service.shutdown();
+        self.serving = Some(false);
+    }
+}
+
+fn is_fresh(build_epoch: u64, max_age: Duration, now: SystemTime) -> bool {
+    let built_at = UNIX_EPOCH + Duration::from_secs(build_epoch);
+    now.duration_since(built_at).map_or(true, |age| age <= max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_fresh() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let now = UNIX_EPOCH + day * 10;
+
+        assert!(is_fresh((day * 9).as_secs(), day * 2, now));
+        assert!(is_fresh((day * 8).as_secs(), day * 2, now));
+        assert!(!is_fresh((day * 7).as_secs(), day * 2, now));
+        assert!(is_fresh((day * 11).as_secs(), day * 2, now));
+    }
+}
@@ -1,60 +1,137 @@
+pub mod access;
+pub mod admin;
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod database;
+#[cfg(test)]
+mod fixture;
+pub mod health;
+pub mod listen;
+pub mod locales;
+pub mod metrics;
 pub mod proto;
-
+pub mod ratelimit;
+pub mod reserved;
+mod shared;
+mod status;
+pub mod tls;
+pub mod trace;
+mod tree;
+pub mod updater;
+
+pub use crate::shared::{create_geo_ip, create_lookup};
+
+use crate::access::Details;
+use crate::cache::ReplyCache;
+use crate::database::{Database, Version};
+use crate::locales::Locales;
 use crate::proto::geoip2::*;
 use crate::proto::geoip2_grpc::*;
+use crate::proto::lookup::LookupReply;
+use crate::reserved::Reserved;
+use crate::shared::LookupAnswer;
+use crate::trace::{Span, TraceContext};
 use futures::prelude::*;
-use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
-use grpcio_health::proto::*;
+use grpcio::{MetadataBuilder, RpcContext, RpcStatus, RpcStatusCode, UnarySink};
 use log::{debug, error};
-use maxminddb::{self, geoip2, MaxMindDBError, Metadata};
+use maxminddb::{geoip2, MaxMindDBError, Metadata};
 use spin::RwLock;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::net::IpAddr;
 use std::sync::Arc;
-
+use std::time::Instant;
+
+/// The public lookup services, `geoip2.GeoIp` and `lookup.Lookup`.
+///
+/// `Reload` belongs to [`admin::AdminService`] and is only served here when a reloader is given,
+/// for clients that predate the admin service. With `empty_if_not_found`, `geoip2.GeoIp` answers addresses
+/// missing from the database with an empty reply and a `found: false` header instead of `NOT_FOUND`, while
+/// `lookup.Lookup` always tells them by the `found` field of its reply.
+///
+/// Serve it with [`create_geo_ip`] and [`create_lookup`], which send the replies held by the cache as they are.
+/// Through `geoip2_grpc::create_geo_ip` each of them is copied first.
 #[derive(Clone)]
-pub struct CityService<T, R>(Arc<RwLock<maxminddb::Reader<T>>>, R)
+pub struct CityService<T, R>(Arc<RwLock<Database<T>>>, Option<R>, bool, Option<ReplyCache>)
 where
     T: AsRef<[u8]>,
-    R: Fn() -> Result<maxminddb::Reader<T>, MaxMindDBError>;
+    R: Fn() -> Result<Version<T>, MaxMindDBError>;
 
 impl<T, R> CityService<T, R>
 where
     T: AsRef<[u8]>,
-    R: Fn() -> Result<maxminddb::Reader<T>, MaxMindDBError>,
+    R: Fn() -> Result<Version<T>, MaxMindDBError>,
 {
-    pub fn new(db: Arc<RwLock<maxminddb::Reader<T>>>, reloader: R) -> CityService<T, R> {
-        CityService(db, reloader)
+    pub fn new(
+        db: Arc<RwLock<Database<T>>>,
+        reloader: Option<R>,
+        empty_if_not_found: bool,
+        cache: Option<ReplyCache>,
+    ) -> CityService<T, R> {
+        CityService(db, reloader, empty_if_not_found, cache)
     }
-}
 
-impl<T, R> GeoIp for CityService<T, R>
-where
-    T: AsRef<[u8]>,
-    R: Fn() -> Result<maxminddb::Reader<T>, MaxMindDBError>,
-{
-    fn lookup(&mut self, ctx: RpcContext<'_>, req: Message, sink: UnarySink<CityReply>) {
-        debug!("received the message: {:?}", req);
+    /// Looks up the city of the address in `req` for the call `name`, recording it in the metrics, the trace and
+    /// the access log.
+    fn find(&self, ctx: &RpcContext<'_>, name: &str, req: Message) -> Result<Arc<CityReply>, RpcStatus> {
+        let started = Instant::now();
+        let mut span = Span::server(name, TraceContext::extract(ctx));
+        span.set_peer(&ctx.peer());
 
         let Message { ip, locales, .. } = req;
-        let result = ip
-            .parse()
+        let parsed = {
+            let _span = span.child("parse");
+            ip.parse()
+        };
+        let result = parsed
             .map_err(|_| {
-                RpcStatus::with_message(
+                status::with_reason(
                     RpcStatusCode::INVALID_ARGUMENT,
                     format!("The request must be IP address but given '{}'", ip),
+                    "INVALID_IP",
+                    subject(Some(ip.as_str()), None),
                 )
             })
-            .and_then(|ip| {
+            .and_then(|addr| {
                 let db = (*self.0).read();
-                match db.lookup::<geoip2::City>(ip) {
-                    Ok(value) => {
-                        let ns = locales.iter().map(|l| l.to_string()).collect::<HashSet<_>>();
-                        Ok(CityReply::from(WrappedCity(value, ns)))
-                    }
-                    Err(err) => Err(convert_error(err)),
-                }
+                let version = db.current().ok_or_else(not_loaded)?;
+                let ns = locales.iter().collect::<Locales>();
+                let cache = self.3.as_ref().map(|cache| (cache, db.generation()));
+                lookup_city(version, cache, addr, ns, &span).map_err(|err| match (&err, reserved::classify(addr)) {
+                    (MaxMindDBError::AddressNotFoundError(_), Some(reserved)) => reserved_error(ip.as_str(), reserved),
+                    _ => convert_error(err, Some(ip.as_str()), Some(&version.reader().metadata)),
+                })
             });
+        if let Err(ref status) = result {
+            span.set_error(status.message());
+        }
+        metrics::observe(ctx, &result, started);
+        let details = Details {
+            ip: Some(ip.as_str()),
+            locales: &locales,
+            country: result.as_ref().ok().map(|reply| reply.get_country().get_iso_code()),
+        };
+        access::log(ctx, &result, details, started);
+        result
+    }
+
+    /// Answers a `geoip2.GeoIp` lookup, along with the `found` header to send with `--empty-if-not-found`.
+    fn answer(&self, ctx: &RpcContext<'_>, req: Message) -> (Option<bool>, Result<Arc<CityReply>, RpcStatus>) {
+        match self.find(ctx, "geoip2.GeoIp/Lookup", req) {
+            Ok(reply) if self.2 => (Some(true), Ok(reply)),
+            Err(status) if self.2 && status.code() == RpcStatusCode::NOT_FOUND => (Some(false), Ok(Arc::default())),
+            result => (None, result),
+        }
+    }
+
+    /// Serves `geoip2.GeoIp/Lookup` for [`create_geo_ip`], sending the reply without copying it.
+    fn lookup_shared(&mut self, ctx: RpcContext<'_>, req: Message, mut sink: UnarySink<Arc<CityReply>>) {
+        debug!("received the message: {:?}", req);
+        let (found, result) = self.answer(&ctx, req);
+        if let Some(found) = found {
+            sink.set_headers(found_header(found));
+        }
 
         let f = match result {
             Ok(reply) => sink.success(reply),
@@ -68,23 +145,97 @@ where
         ctx.spawn(f)
     }
 
+    /// Serves `lookup.Lookup/Lookup` for [`create_lookup`], sending the city without copying it.
+    fn lookup_found(&mut self, ctx: RpcContext<'_>, req: Message, sink: UnarySink<LookupAnswer>) {
+        debug!("received the message: {:?}", req);
+        let result = match self.find(&ctx, "lookup.Lookup/Lookup", req) {
+            Ok(city) => {
+                let mut reply = LookupReply::default();
+                reply.set_found(true);
+                Ok(LookupAnswer {
+                    reply,
+                    city: Some(city),
+                })
+            }
+            Err(status) if status.code() == RpcStatusCode::NOT_FOUND => Ok(LookupAnswer {
+                reply: not_found_reply(&status),
+                city: None,
+            }),
+            Err(status) => Err(status),
+        };
+
+        let f = match result {
+            Ok(answer) => sink.success(answer),
+            Err(status) => sink.fail(status),
+        };
+
+        let f = f
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+
+        ctx.spawn(f)
+    }
+}
+
+impl<T, R> GeoIp for CityService<T, R>
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<Version<T>, MaxMindDBError>,
+{
+    fn lookup(&mut self, ctx: RpcContext<'_>, req: Message, mut sink: UnarySink<CityReply>) {
+        debug!("received the message: {:?}", req);
+        let (found, result) = self.answer(&ctx, req);
+        if let Some(found) = found {
+            sink.set_headers(found_header(found));
+        }
+
+        // The sink takes the reply, so one shared with the cache is copied; create_geo_ip avoids that.
+        let f = match result {
+            Ok(reply) => sink.success(Arc::unwrap_or_clone(reply)),
+            Err(status) => sink.fail(status),
+        };
+
+        let f = f
+            .map_err(move |err| error!("failed to reply, cause: {:?}", err))
+            .map(|_| ());
+
+        ctx.spawn(f)
+    }
+
     fn metadata(&mut self, ctx: RpcContext<'_>, _req: Empty, sink: UnarySink<MetadataReply>) {
-        let result = MetadataReply::from(&self.0.read().metadata);
-        let f = sink
-            .success(result)
+        let started = Instant::now();
+        let result = self
+            .0
+            .read()
+            .reader()
+            .map(|reader| MetadataReply::from(&reader.metadata))
+            .ok_or_else(not_loaded);
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
+
+        let f = match result {
+            Ok(reply) => sink.success(reply),
+            Err(status) => sink.fail(status),
+        };
+
+        let f = f
             .map_err(move |err| error!("failed to reply, cause: {:?}", err))
             .map(|_| ());
+
         ctx.spawn(f)
     }
 
     fn reload(&mut self, ctx: RpcContext<'_>, _req: Empty, sink: UnarySink<MetadataReply>) {
-        let result = self.1()
-            .map(|reader| {
-                let mut guard = self.0.write();
-                *guard = reader;
-                MetadataReply::from(&guard.metadata)
-            })
-            .map_err(convert_error);
+        let started = Instant::now();
+        let result = match self.1 {
+            Some(ref reloader) => admin::reload(&self.0, reloader),
+            None => Err(RpcStatus::with_message(
+                RpcStatusCode::UNIMPLEMENTED,
+                "Reload is served by admin.Admin".to_string(),
+            )),
+        };
+        metrics::observe(&ctx, &result, started);
+        access::log(&ctx, &result, Details::default(), started);
 
         let f = match result {
             Ok(reply) => sink.success(reply),
@@ -101,22 +252,42 @@ where
 
 impl Display for Message_Locale {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let data = match self {
-            Message_Locale::BRAZLIAN_PORTUGUESE => "pt-BR",
-            Message_Locale::ENGLISH => "en",
-            Message_Locale::FRENCH => "fr",
-            Message_Locale::GERMAN => "de",
-            Message_Locale::JAPANESE => "ja",
-            Message_Locale::RUSSIAN => "ru",
-            Message_Locale::SIMPLIFIED_CHINESE => "zh-CN",
-            Message_Locale::SPANISH => "es",
-            Message_Locale::UNSPECIFIED => "", // TODO: should it panic?
+        f.write_str(locales::code(*self))
+    }
+}
+
+/// Looks `addr` up in `version`, converting its record to a reply in `locales`. Given a cache and the generation of
+/// the database, the reply is converted only the first time an address of the record is looked up and shared after.
+fn lookup_city<T>(
+    version: &Version<T>,
+    cache: Option<(&ReplyCache, u64)>,
+    addr: IpAddr,
+    locales: Locales,
+    span: &Span,
+) -> Result<Arc<CityReply>, MaxMindDBError>
+where
+    T: AsRef<[u8]>,
+{
+    let lookup = || -> Result<CityReply, MaxMindDBError> {
+        let value = {
+            let _span = span.child("lookup");
+            version.reader().lookup::<geoip2::City>(addr)?
         };
-        f.write_str(data)
+        let _span = span.child("convert");
+        Ok(convert(value, locales))
+    };
+    match (cache, version.record(addr)) {
+        (Some((cache, generation)), Some(record)) => cache.get_or_insert_with(generation, record, locales, lookup),
+        _ => lookup().map(Arc::new),
     }
 }
 
-struct WrappedCity<'a>(geoip2::City<'a>, HashSet<String>);
+/// Converts a record of a city database to a reply carrying the names in `locales`, as a lookup does.
+pub fn convert(city: geoip2::City<'_>, locales: Locales) -> CityReply {
+    CityReply::from(WrappedCity(city, locales))
+}
+
+struct WrappedCity<'a>(geoip2::City<'a>, Locales);
 
 impl<'a> From<WrappedCity<'a>> for CityReply {
     fn from(geo_city: WrappedCity) -> CityReply {
@@ -125,15 +296,15 @@ impl<'a> From<WrappedCity<'a>> for CityReply {
         let filter = geo_city.1;
 
         if let Some(c) = geo_city.0.city {
-            reply.set_city(City::from(MCity(c, &filter)));
+            reply.set_city(City::from(MCity(c, filter)));
         }
 
         if let Some(c) = geo_city.0.continent {
-            reply.set_continent(Continent::from(MContinent(c, &filter)));
+            reply.set_continent(Continent::from(MContinent(c, filter)));
         }
 
         if let Some(c) = geo_city.0.country {
-            reply.set_country(Country::from(MCountry(c, &filter)));
+            reply.set_country(Country::from(MCountry(c, filter)));
         }
 
         if let Some(c) = geo_city.0.location {
@@ -145,11 +316,11 @@ impl<'a> From<WrappedCity<'a>> for CityReply {
         }
 
         if let Some(c) = geo_city.0.registered_country {
-            reply.set_registered_country(Country::from(MCountry(c, &filter)));
+            reply.set_registered_country(Country::from(MCountry(c, filter)));
         }
 
         if let Some(c) = geo_city.0.represented_country {
-            reply.set_represented_country(RepresentedCountry::from(MRepresentedCountry(c, &filter)));
+            reply.set_represented_country(RepresentedCountry::from(MRepresentedCountry(c, filter)));
         }
 
         if let Some(xs) = geo_city.0.subdivisions {
@@ -166,7 +337,7 @@ impl<'a> From<WrappedCity<'a>> for CityReply {
     }
 }
 
-struct MCity<'a>(geoip2::city::City<'a>, &'a HashSet<String>);
+struct MCity<'a>(geoip2::city::City<'a>, Locales);
 
 impl<'a> From<MCity<'a>> for City {
     fn from(c: MCity) -> City {
@@ -181,7 +352,7 @@ impl<'a> From<MCity<'a>> for City {
     }
 }
 
-struct MContinent<'a>(geoip2::city::Continent<'a>, &'a HashSet<String>);
+struct MContinent<'a>(geoip2::city::Continent<'a>, Locales);
 
 impl<'a> From<MContinent<'a>> for Continent {
     fn from(c: MContinent) -> Continent {
@@ -199,7 +370,7 @@ impl<'a> From<MContinent<'a>> for Continent {
     }
 }
 
-struct MCountry<'a>(geoip2::city::Country<'a>, &'a HashSet<String>);
+struct MCountry<'a>(geoip2::city::Country<'a>, Locales);
 
 impl<'a> From<MCountry<'a>> for Country {
     fn from(c: MCountry) -> Country {
@@ -214,7 +385,7 @@ impl<'a> From<MCountry<'a>> for Country {
             r.set_iso_code(a.to_string());
         }
         if let Some(n) = &c.0.names {
-            r.set_names(filter_locales(n, c.1)); // TODO: clone
+            r.set_names(filter_locales(n, c.1));
         }
         r
     }
@@ -249,7 +420,7 @@ impl<'a> From<geoip2::city::Postal<'a>> for Postal {
     }
 }
 
-struct MRepresentedCountry<'a>(geoip2::city::RepresentedCountry<'a>, &'a HashSet<String>);
+struct MRepresentedCountry<'a>(geoip2::city::RepresentedCountry<'a>, Locales);
 
 impl<'a> From<MRepresentedCountry<'a>> for RepresentedCountry {
     fn from(c: MRepresentedCountry) -> RepresentedCountry {
@@ -301,24 +472,84 @@ impl From<geoip2::city::Traits> for Traits {
     }
 }
 
-fn convert_error(err: MaxMindDBError) -> RpcStatus {
-    match err {
-        MaxMindDBError::AddressNotFoundError(msg) => RpcStatus::with_message(RpcStatusCode::NOT_FOUND, msg),
-        MaxMindDBError::InvalidNetworkError(msg) => RpcStatus::with_message(RpcStatusCode::INTERNAL, msg),
-        MaxMindDBError::InvalidDatabaseError(msg) => RpcStatus::with_message(RpcStatusCode::INTERNAL, msg),
-        MaxMindDBError::IoError(msg) => RpcStatus::with_message(RpcStatusCode::INTERNAL, msg),
-        MaxMindDBError::MapError(msg) => RpcStatus::with_message(RpcStatusCode::INTERNAL, msg),
-        MaxMindDBError::DecodingError(msg) => RpcStatus::with_message(RpcStatusCode::INTERNAL, msg),
+/// Converts a database error of a call about `ip`, answered from the database described by `metadata`.
+pub(crate) fn convert_error(err: MaxMindDBError, ip: Option<&str>, metadata: Option<&Metadata>) -> RpcStatus {
+    let (code, reason, msg) = match err {
+        MaxMindDBError::AddressNotFoundError(msg) => (RpcStatusCode::NOT_FOUND, "ADDRESS_NOT_FOUND", msg),
+        MaxMindDBError::InvalidNetworkError(msg) => (RpcStatusCode::INTERNAL, "INVALID_NETWORK", msg),
+        MaxMindDBError::InvalidDatabaseError(msg) => (RpcStatusCode::INTERNAL, "DATABASE_CORRUPT", msg),
+        MaxMindDBError::IoError(msg) => (RpcStatusCode::INTERNAL, "DATABASE_UNREADABLE", msg),
+        MaxMindDBError::MapError(msg) => (RpcStatusCode::INTERNAL, "DATABASE_UNREADABLE", msg),
+        MaxMindDBError::DecodingError(msg) => (RpcStatusCode::INTERNAL, "DATABASE_CORRUPT", msg),
+    };
+    status::with_reason(code, msg, reason, subject(ip, metadata))
+}
+
+/// Tells that `ip` is missing from the database because it belongs to a special-purpose block rather than
+/// to a network that could be located. The code is still `NOT_FOUND`, as for any other miss, and only the reason
+/// tells them apart.
+fn reserved_error(ip: &str, reserved: &Reserved) -> RpcStatus {
+    status::with_reason(
+        RpcStatusCode::NOT_FOUND,
+        format!(
+            "{} is in the {} block {} ({})",
+            ip, reserved.name, reserved, reserved.rfc
+        ),
+        "RESERVED_ADDRESS",
+        vec![
+            ("ip", ip.to_string()),
+            ("block", reserved.to_string()),
+            ("registry_name", reserved.name.to_string()),
+            ("rfc", reserved.rfc.to_string()),
+        ],
+    )
+}
+
+/// The `lookup.Lookup` reply to a lookup that failed with `status`, carrying the reason `geoip2.GeoIp` gives.
+fn not_found_reply(status: &RpcStatus) -> LookupReply {
+    let mut reply = LookupReply::default();
+    if let Some(info) = status::error_info(status) {
+        reply.set_error_info(info);
     }
+    reply
+}
+
+/// Tells a lookup answered with an empty reply because the address is missing from the database from one of an
+/// address the database knows little about.
+fn found_header(found: bool) -> grpcio::Metadata {
+    let mut headers = MetadataBuilder::with_capacity(1);
+    headers.add_str("found", &found.to_string()).unwrap();
+    headers.build()
 }
 
-fn filter_locales<'a>(names: &'a BTreeMap<&'a str, &'a str>, filter: &'a HashSet<String>) -> HashMap<String, String> {
-    let cap = if filter.is_empty() { names.len() } else { filter.len() };
-    let mut h = HashMap::with_capacity(cap);
+/// The `ErrorInfo` metadata naming the IP address and the database a call was about.
+fn subject(ip: Option<&str>, metadata: Option<&Metadata>) -> Vec<(&'static str, String)> {
+    let mut subject = Vec::with_capacity(3);
+    if let Some(ip) = ip {
+        subject.push(("ip", ip.to_string()));
+    }
+    if let Some(metadata) = metadata {
+        subject.push(("database_type", metadata.database_type.clone()));
+        subject.push(("build_epoch", metadata.build_epoch.to_string()));
+    }
+    subject
+}
+
+/// The answer to calls received before the database file appeared.
+pub(crate) fn not_loaded() -> RpcStatus {
+    status::with_reason(
+        RpcStatusCode::UNAVAILABLE,
+        "The database is not loaded yet".to_string(),
+        "DATABASE_NOT_LOADED",
+        Vec::new(),
+    )
+}
+
+fn filter_locales(names: &BTreeMap<&str, &str>, filter: Locales) -> HashMap<String, String> {
+    let mut h = HashMap::with_capacity(filter.capacity(names.len()));
     for (k, v) in names.iter() {
-        let k = k.to_string();
-        if filter.is_empty() || filter.contains(&k) {
-            h.insert(k, v.to_string());
+        if filter.contains(k) {
+            h.insert(k.to_string(), v.to_string());
         }
     }
     h
@@ -348,53 +579,106 @@ impl From<&Metadata> for MetadataReply {
     }
 }
 
-#[derive(Clone)]
-pub struct HealthService;
-
-impl Health for HealthService {
-    fn check(&mut self, ctx: RpcContext<'_>, req: HealthCheckRequest, sink: UnarySink<HealthCheckResponse>) {
-        debug!("check the service: {}", req.get_service());
-        let mut resp = HealthCheckResponse::default();
-        resp.set_status(ServingStatus::Serving);
-        ctx.spawn(
-            sink.success(resp)
-                .map_err(|e| error!("failed to report result: {:?}", e))
-                .map(|_| ()),
-        );
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proto::error_details::ErrorInfo;
+    use crate::proto::status::Status;
+    use protobuf::Message as _;
+
+    #[test]
+    fn test_convert_error() {
+        let status = convert_error(
+            MaxMindDBError::AddressNotFoundError("Address not found in database".to_string()),
+            Some("192.0.2.1"),
+            None,
+        );
+        assert_eq!(status.code(), RpcStatusCode::NOT_FOUND);
+        assert_eq!(status.message(), "Address not found in database");
+
+        let details = Status::parse_from_bytes(status.details()).unwrap();
+        assert_eq!(details.get_details().len(), 1);
+        let info = ErrorInfo::parse_from_bytes(details.get_details()[0].get_value()).unwrap();
+        assert_eq!(info.get_reason(), "ADDRESS_NOT_FOUND");
+        assert_eq!(info.get_domain(), status::DOMAIN);
+        assert_eq!(info.get_metadata().get("ip").map(String::as_str), Some("192.0.2.1"));
+    }
+
+    #[test]
+    fn test_reserved_error() {
+        let reserved = reserved::classify("::ffff:10.0.0.1".parse().unwrap()).unwrap();
+        let status = reserved_error("::ffff:10.0.0.1", reserved);
+        assert_eq!(status.code(), RpcStatusCode::NOT_FOUND);
+
+        let details = Status::parse_from_bytes(status.details()).unwrap();
+        let info = ErrorInfo::parse_from_bytes(details.get_details()[0].get_value()).unwrap();
+        assert_eq!(info.get_reason(), "RESERVED_ADDRESS");
+        assert_eq!(info.get_metadata().get("block").map(String::as_str), Some("10.0.0.0/8"));
+
+        let reply = not_found_reply(&status);
+        assert!(!reply.get_found());
+        assert_eq!(reply.get_error_info(), &info);
+    }
+
+    #[test]
+    fn test_lookup_city_shares_record() {
+        let version = Version::from_source("1.mmdb".to_string(), crate::fixture::database(1)).unwrap();
+        let cache = ReplyCache::new(std::num::NonZeroUsize::new(16).unwrap());
+        let span = Span::internal("test");
+
+        // The fixture maps network k and k + 256 onto the same record.
+        let first = lookup_city(
+            &version,
+            Some((&cache, 1)),
+            "10.0.0.1".parse().unwrap(),
+            Locales::all(),
+            &span,
+        );
+        let second = lookup_city(
+            &version,
+            Some((&cache, 1)),
+            "26.0.0.1".parse().unwrap(),
+            Locales::all(),
+            &span,
+        );
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+
+        let uncached = lookup_city(&version, None, "10.0.0.1".parse().unwrap(), Locales::all(), &span).unwrap();
+        let again = lookup_city(&version, None, "10.0.0.1".parse().unwrap(), Locales::all(), &span).unwrap();
+        assert!(!Arc::ptr_eq(&uncached, &again));
+        assert_eq!(uncached, again);
+    }
 
     #[test]
     fn test_filter_locales() {
         let mut src = BTreeMap::new();
-        src.insert("1", "one");
-        src.insert("2", "two");
-        src.insert("3", "three");
-        src.insert("4", "four");
-
-        let mut filters = HashSet::new();
-        filters.insert("11".to_string());
-        filters.insert("2".to_string());
-        filters.insert("3".to_string());
-        let actual = filter_locales(&src, &filters);
+        src.insert("de", "Tokio");
+        src.insert("en", "Tokyo");
+        src.insert("ja", "東京");
+        src.insert("ko", "도쿄");
+
+        let filters = [
+            Message_Locale::SPANISH,
+            Message_Locale::ENGLISH,
+            Message_Locale::JAPANESE,
+        ]
+        .iter()
+        .collect();
+        let actual = filter_locales(&src, filters);
 
         let mut expected = HashMap::new();
-        expected.insert("2".to_string(), "two".to_string());
-        expected.insert("3".to_string(), "three".to_string());
+        expected.insert("en".to_string(), "Tokyo".to_string());
+        expected.insert("ja".to_string(), "東京".to_string());
         assert_eq!(actual, expected);
 
-        let filters = HashSet::new();
-        let actual = filter_locales(&src, &filters);
+        let filters = Locales::all();
+        let actual = filter_locales(&src, filters);
 
         let mut expected = HashMap::new();
-        expected.insert("1".to_string(), "one".to_string());
-        expected.insert("2".to_string(), "two".to_string());
-        expected.insert("3".to_string(), "three".to_string());
-        expected.insert("4".to_string(), "four".to_string());
+        expected.insert("de".to_string(), "Tokio".to_string());
+        expected.insert("en".to_string(), "Tokyo".to_string());
+        expected.insert("ja".to_string(), "東京".to_string());
+        expected.insert("ko".to_string(), "도쿄".to_string());
         assert_eq!(actual, expected);
     }
 }
@@ -0,0 +1,303 @@
+use crate::tls::TlsFiles;
+use std::fmt::{self, Display};
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An address the server accepts connections on.
+///
+/// Parsed from `host:port` or `unix:///absolute/path`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn socket_path(&self) -> Option<&Path> {
+        match self {
+            Address::Tcp(_) => None,
+            Address::Unix(path) => Some(path),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Address, String> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                return Err(format!("The socket path must be absolute but given '{}'", s));
+            }
+            Ok(Address::Unix(PathBuf::from(path)))
+        } else if s
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        {
+            Ok(Address::Tcp(s.to_string()))
+        } else {
+            Err(format!(
+                "The address must be 'host:port' or 'unix:///path' but given '{}'",
+                s
+            ))
+        }
+    }
+}
+
+/// Formats the address the way gRPC expects it.
+impl Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => f.write_str(addr),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The services registered on a listener, given as `geoip+admin+health`.
+///
+/// The admin service changes what the server answers, so it is only registered when asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Services {
+    pub geoip: bool,
+    pub admin: bool,
+    pub health: bool,
+}
+
+impl Services {
+    /// The services of an `--admin-listen` listener.
+    pub fn admin() -> Services {
+        Services {
+            geoip: false,
+            admin: true,
+            health: false,
+        }
+    }
+}
+
+impl Default for Services {
+    fn default() -> Services {
+        Services {
+            geoip: true,
+            admin: false,
+            health: true,
+        }
+    }
+}
+
+impl FromStr for Services {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Services, String> {
+        let mut services = Services {
+            geoip: false,
+            admin: false,
+            health: false,
+        };
+        for name in s.split('+') {
+            match name {
+                "geoip" => services.geoip = true,
+                "admin" => services.admin = true,
+                "health" => services.health = true,
+                _ => return Err(format!("Unknown service '{}', expected geoip, admin or health", name)),
+            }
+        }
+        Ok(services)
+    }
+}
+
+/// How connections to a listener are secured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Security {
+    /// Uses the server wide `--tls-*` files on TCP, plaintext on unix sockets.
+    Default,
+    Plaintext,
+    Tls(TlsFiles),
+}
+
+/// A listener given as `address[,option=value...]`.
+///
+/// The options are `services=geoip+admin+health` (`geoip+health` by default), `tls=off`, and `tls-cert`,
+/// `tls-key` and `tls-client-ca` to use other files than the server wide ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listen {
+    pub address: Address,
+    pub services: Services,
+    pub security: Security,
+}
+
+impl Listen {
+    pub fn new(address: Address) -> Listen {
+        Listen {
+            address,
+            services: Services::default(),
+            security: Security::Default,
+        }
+    }
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Listen, String> {
+        let mut parts = s.split(',');
+        let mut listen = Listen::new(parts.next().unwrap_or_default().parse()?);
+        let (mut cert, mut key, mut client_ca) = (None, None, None);
+        for option in parts {
+            let (name, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("The option must be 'name=value' but given '{}'", option))?;
+            match name {
+                "services" => listen.services = value.parse()?,
+                "tls" if value == "off" => listen.security = Security::Plaintext,
+                "tls-cert" => cert = Some(PathBuf::from(value)),
+                "tls-key" => key = Some(PathBuf::from(value)),
+                "tls-client-ca" => client_ca = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown listener option '{}'", option)),
+            }
+        }
+        match (cert, key) {
+            (Some(cert), Some(key)) => listen.security = Security::Tls(TlsFiles { cert, key, client_ca }),
+            (None, None) if client_ca.is_none() => {}
+            _ => return Err(format!("Both tls-cert and tls-key must be given in '{}'", s)),
+        }
+        Ok(listen)
+    }
+}
+
+impl Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.address.fmt(f)
+    }
+}
+
+/// The owner of a socket file, given as `uid[:gid]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Owner, String> {
+        let parse = |id: &str| -> Result<Option<u32>, String> {
+            if id.is_empty() {
+                Ok(None)
+            } else {
+                id.parse()
+                    .map(Some)
+                    .map_err(|_| format!("The owner must be 'uid[:gid]' but given '{}'", s))
+            }
+        };
+        let (uid, gid) = s.split_once(':').unwrap_or((s, ""));
+        Ok(Owner {
+            uid: parse(uid)?,
+            gid: parse(gid)?,
+        })
+    }
+}
+
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|_| format!("The mode must be octal like 660 but given '{}'", s))
+}
+
+/// Applies the mode and owner to a socket file created by the server.
+pub fn set_permissions(path: &Path, mode: Option<u32>, owner: Option<Owner>) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    if let Some(owner) = owner {
+        std::os::unix::fs::chown(path, owner.uid, owner.gid)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            "unix:///run/mmdb.sock".parse(),
+            Ok(Address::Unix(PathBuf::from("/run/mmdb.sock")))
+        );
+        assert_eq!(
+            "localhost:50000".parse(),
+            Ok(Address::Tcp("localhost:50000".to_string()))
+        );
+        assert_eq!("[::1]:50000".parse(), Ok(Address::Tcp("[::1]:50000".to_string())));
+        assert!("unix://run/mmdb.sock".parse::<Address>().is_err());
+        assert!("localhost".parse::<Address>().is_err());
+        assert!(":50000".parse::<Address>().is_err());
+
+        let address: Address = "unix:///run/mmdb.sock".parse().unwrap();
+        assert_eq!(address.to_string(), "unix:/run/mmdb.sock");
+    }
+
+    #[test]
+    fn test_parse_listen() {
+        let listen: Listen = "0.0.0.0:50000".parse().unwrap();
+        assert_eq!(listen, Listen::new(Address::Tcp("0.0.0.0:50000".to_string())));
+
+        let listen: Listen = "127.0.0.1:50001,services=admin+health,tls=off".parse().unwrap();
+        assert_eq!(
+            listen.services,
+            Services {
+                geoip: false,
+                admin: true,
+                health: true
+            }
+        );
+        assert_eq!(listen.security, Security::Plaintext);
+
+        let listen: Listen = "[::]:50000,tls-cert=/etc/mmdb/tls.crt,tls-key=/etc/mmdb/tls.key"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            listen.security,
+            Security::Tls(TlsFiles {
+                cert: PathBuf::from("/etc/mmdb/tls.crt"),
+                key: PathBuf::from("/etc/mmdb/tls.key"),
+                client_ca: None
+            })
+        );
+
+        assert!("[::]:50000,services=lookup".parse::<Listen>().is_err());
+        assert!("[::]:50000,tls-cert=/etc/mmdb/tls.crt".parse::<Listen>().is_err());
+        assert!("[::]:50000,port=1".parse::<Listen>().is_err());
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(
+            "1000:1001".parse(),
+            Ok(Owner {
+                uid: Some(1000),
+                gid: Some(1001)
+            })
+        );
+        assert_eq!(
+            "1000".parse(),
+            Ok(Owner {
+                uid: Some(1000),
+                gid: None
+            })
+        );
+        assert_eq!(
+            ":1001".parse(),
+            Ok(Owner {
+                uid: None,
+                gid: Some(1001)
+            })
+        );
+        assert!("mmdb".parse::<Owner>().is_err());
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert!(parse_mode("rw").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::proto::geoip2::Message_Locale;
+use std::iter::FromIterator;
+
+/// The codes of the names in a database, by locale.
+const CODES: [(Message_Locale, &str); 9] = [
+    (Message_Locale::UNSPECIFIED, ""), // TODO: should it panic?
+    (Message_Locale::BRAZLIAN_PORTUGUESE, "pt-BR"),
+    (Message_Locale::ENGLISH, "en"),
+    (Message_Locale::FRENCH, "fr"),
+    (Message_Locale::GERMAN, "de"),
+    (Message_Locale::JAPANESE, "ja"),
+    (Message_Locale::RUSSIAN, "ru"),
+    (Message_Locale::SIMPLIFIED_CHINESE, "zh-CN"),
+    (Message_Locale::SPANISH, "es"),
+];
+
+pub(crate) fn code(locale: Message_Locale) -> &'static str {
+    CODES.iter().find(|(l, _)| *l == locale).map_or("", |(_, code)| code)
+}
+
+/// The locale of a code such as `en` or `pt-BR`.
+pub fn parse(code: &str) -> Result<Message_Locale, String> {
+    CODES
+        .iter()
+        .skip(1)
+        .find(|(_, c)| *c == code)
+        .map(|(locale, _)| *locale)
+        .ok_or_else(|| format!("Unknown locale '{}'", code))
+}
+
+/// The locales whose names a reply carries, all of them when none is asked for.
+///
+/// Held as bits rather than strings, so that a request is parsed once without allocating and names are
+/// only copied when they pass the filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Locales(u16);
+
+impl Locales {
+    /// Every locale, as asked for by an empty list.
+    pub fn all() -> Locales {
+        Locales(0)
+    }
+
+    pub fn is_all(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the names in the locale of `code` pass the filter.
+    pub fn contains(self, code: &str) -> bool {
+        self.is_all()
+            || CODES
+                .iter()
+                .enumerate()
+                .any(|(i, (_, c))| self.0 & (1 << i) != 0 && *c == code)
+    }
+
+    /// How many names pass the filter at most, out of `names`.
+    pub fn capacity(self, names: usize) -> usize {
+        if self.is_all() {
+            names
+        } else {
+            names.min(self.0.count_ones() as usize)
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a Message_Locale> for Locales {
+    fn from_iter<I: IntoIterator<Item = &'a Message_Locale>>(locales: I) -> Locales {
+        Locales(locales.into_iter().fold(0, |bits, locale| {
+            bits | CODES.iter().position(|(l, _)| l == locale).map_or(0, |i| 1 << i)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let locales: Locales = [Message_Locale::JAPANESE, Message_Locale::ENGLISH].iter().collect();
+        assert!(locales.contains("ja"));
+        assert!(locales.contains("en"));
+        assert!(!locales.contains("fr"));
+        assert!(!locales.contains("ko"));
+        assert_eq!(locales.capacity(8), 2);
+
+        let all: Locales = [].iter().collect();
+        assert_eq!(all, Locales::all());
+        assert!(all.contains("fr"));
+        assert!(all.contains("ko"));
+        assert_eq!(all.capacity(8), 8);
+
+        let unspecified: Locales = [Message_Locale::UNSPECIFIED].iter().collect();
+        assert!(!unspecified.is_all());
+        assert!(!unspecified.contains("en"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("pt-BR"), Ok(Message_Locale::BRAZLIAN_PORTUGUESE));
+        assert_eq!(parse("en"), Ok(Message_Locale::ENGLISH));
+        assert!(parse("").is_err());
+        assert!(parse("ko").is_err());
+    }
+}
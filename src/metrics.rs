@@ -0,0 +1,389 @@
+use crate::database::Database;
+use crate::ratelimit::RateLimiter;
+use grpcio::{RpcContext, RpcStatus, RpcStatusCode};
+use log::{error, info};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder, TEXT_FORMAT,
+};
+use spin::{Mutex, RwLock};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a scraper may take to send its request or read the metrics.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The methods whose share of `NOT_FOUND` is exported.
+const LOOKUP_METHODS: [&str; 2] = ["geoip2.GeoIp/Lookup", "lookup.Lookup/Lookup"];
+
+const CODES: [(RpcStatusCode, &str); 17] = [
+    (RpcStatusCode::OK, "OK"),
+    (RpcStatusCode::CANCELLED, "CANCELLED"),
+    (RpcStatusCode::UNKNOWN, "UNKNOWN"),
+    (RpcStatusCode::INVALID_ARGUMENT, "INVALID_ARGUMENT"),
+    (RpcStatusCode::DEADLINE_EXCEEDED, "DEADLINE_EXCEEDED"),
+    (RpcStatusCode::NOT_FOUND, "NOT_FOUND"),
+    (RpcStatusCode::ALREADY_EXISTS, "ALREADY_EXISTS"),
+    (RpcStatusCode::PERMISSION_DENIED, "PERMISSION_DENIED"),
+    (RpcStatusCode::RESOURCE_EXHAUSTED, "RESOURCE_EXHAUSTED"),
+    (RpcStatusCode::FAILED_PRECONDITION, "FAILED_PRECONDITION"),
+    (RpcStatusCode::ABORTED, "ABORTED"),
+    (RpcStatusCode::OUT_OF_RANGE, "OUT_OF_RANGE"),
+    (RpcStatusCode::UNIMPLEMENTED, "UNIMPLEMENTED"),
+    (RpcStatusCode::INTERNAL, "INTERNAL"),
+    (RpcStatusCode::UNAVAILABLE, "UNAVAILABLE"),
+    (RpcStatusCode::DATA_LOSS, "DATA_LOSS"),
+    (RpcStatusCode::UNAUTHENTICATED, "UNAUTHENTICATED"),
+];
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new("mmdb_grpc_requests_total", "Calls answered by the services."),
+        &["method", "code"],
+    )
+    .unwrap()
+});
+
+static LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new("mmdb_grpc_request_duration_seconds", "Time spent answering a call.")
+            .buckets(exponential_buckets(0.0001, 2.0, 14).unwrap()),
+        &["method"],
+    )
+    .unwrap()
+});
+
+static DROPPED_SPANS: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "mmdb_trace_dropped_spans_total",
+        "Spans dropped because the exporter queue was full.",
+    )
+    .unwrap()
+});
+
+static DROPPED_ACCESS_LOG: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "mmdb_access_log_dropped_total",
+        "Access log lines dropped because the writer was behind.",
+    )
+    .unwrap()
+});
+
+static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "mmdb_cache_lookups_total",
+            "Lookups answered from the reply cache or not.",
+        ),
+        &["result"],
+    )
+    .unwrap()
+});
+
+/// The name of `code` in the gRPC specification, e.g. `NOT_FOUND`.
+pub(crate) fn code_name(code: RpcStatusCode) -> &'static str {
+    CODES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map_or("UNKNOWN", |(_, name)| name)
+}
+
+fn method(ctx: &RpcContext<'_>) -> String {
+    let method = String::from_utf8_lossy(ctx.method());
+    method.strip_prefix('/').unwrap_or(&method).to_string()
+}
+
+/// Records a call answered with `result`, `started` when it was received.
+pub(crate) fn observe<R>(ctx: &RpcContext<'_>, result: &Result<R, RpcStatus>, started: Instant) {
+    let method = method(ctx);
+    let code = match result {
+        Ok(_) => RpcStatusCode::OK,
+        Err(status) => status.code(),
+    };
+    REQUESTS.with_label_values(&[&method, code_name(code)]).inc();
+    LATENCY
+        .with_label_values(&[&method])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Records a call rejected by a checker with `status`, before it reached the service.
+pub(crate) fn observe_rejected(ctx: &RpcContext<'_>, status: &RpcStatus) {
+    REQUESTS
+        .with_label_values(&[&method(ctx), code_name(status.code())])
+        .inc();
+}
+
+/// Records a span that could not be queued for export.
+pub(crate) fn observe_dropped_span() {
+    DROPPED_SPANS.inc();
+}
+
+/// Records an access log line that could not be queued for writing.
+pub(crate) fn observe_dropped_access_log() {
+    DROPPED_ACCESS_LOG.inc();
+}
+
+/// Records whether a lookup found its reply in the cache.
+pub(crate) fn observe_cache(hit: bool) {
+    CACHE_LOOKUPS
+        .with_label_values(&[if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+/// Values read from the database and the rate limiter when scraped.
+struct StateCollector<T>
+where
+    T: AsRef<[u8]>,
+{
+    db: Arc<RwLock<Database<T>>>,
+    limiter: Option<RateLimiter>,
+    build_epoch: IntGauge,
+    node_count: IntGauge,
+    reloads: IntCounterVec,
+    not_found_ratio: GaugeVec,
+    limiter_buckets: IntGaugeVec,
+    limiter_exhausted: IntGaugeVec,
+    limiter_rejected: IntCounter,
+    // Serializes scrapes, which reset and refill the metrics above.
+    lock: Mutex<()>,
+}
+
+impl<T> StateCollector<T>
+where
+    T: AsRef<[u8]>,
+{
+    fn new(db: Arc<RwLock<Database<T>>>, limiter: Option<RateLimiter>) -> prometheus::Result<StateCollector<T>> {
+        Ok(StateCollector {
+            db,
+            limiter,
+            build_epoch: IntGauge::new("mmdb_database_build_epoch", "Build time of the served database.")?,
+            node_count: IntGauge::new(
+                "mmdb_database_node_count",
+                "Nodes in the search tree of the served database.",
+            )?,
+            reloads: IntCounterVec::new(
+                Opts::new("mmdb_reloads_total", "Attempts to reload the database."),
+                &["result"],
+            )?,
+            not_found_ratio: GaugeVec::new(
+                Opts::new(
+                    "mmdb_lookup_not_found_ratio",
+                    "Share of the lookups answered with NOT_FOUND.",
+                ),
+                &["method"],
+            )?,
+            limiter_buckets: IntGaugeVec::new(
+                Opts::new(
+                    "mmdb_rate_limit_buckets",
+                    "Clients whose bucket has not refilled since their last call.",
+                ),
+                &["method"],
+            )?,
+            limiter_exhausted: IntGaugeVec::new(
+                Opts::new(
+                    "mmdb_rate_limit_exhausted_buckets",
+                    "Clients whose next call would be rejected.",
+                ),
+                &["method"],
+            )?,
+            limiter_rejected: IntCounter::new("mmdb_rate_limit_rejected_total", "Calls rejected by the rate limiter.")?,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn update(&self) {
+        {
+            let db = self.db.read();
+            if let Some(reader) = db.reader() {
+                self.build_epoch.set(reader.metadata.build_epoch as i64);
+                self.node_count.set(reader.metadata.node_count as i64);
+            }
+
+            let reloads = db.reloads();
+            self.reloads.reset();
+            self.reloads.with_label_values(&["success"]).inc_by(reloads.succeeded);
+            self.reloads.with_label_values(&["failure"]).inc_by(reloads.failed);
+        }
+
+        for method in LOOKUP_METHODS {
+            let count = |code: &str| REQUESTS.with_label_values(&[method, code]).get();
+            let total: u64 = CODES.iter().map(|(_, name)| count(*name)).sum();
+            if total > 0 {
+                self.not_found_ratio
+                    .with_label_values(&[method])
+                    .set(count("NOT_FOUND") as f64 / total as f64);
+            }
+        }
+
+        if let Some(ref limiter) = self.limiter {
+            // Clients are not labelled, as there may be as many as there are addresses.
+            self.limiter_buckets.reset();
+            self.limiter_exhausted.reset();
+            for bucket in limiter.state() {
+                self.limiter_buckets.with_label_values(&[&bucket.method]).inc();
+                if bucket.tokens < 1.0 {
+                    self.limiter_exhausted.with_label_values(&[&bucket.method]).inc();
+                }
+            }
+            self.limiter_rejected.reset();
+            self.limiter_rejected.inc_by(limiter.rejected());
+        }
+    }
+}
+
+impl<T> Collector for StateCollector<T>
+where
+    T: AsRef<[u8]> + Send + Sync + 'static,
+{
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.build_epoch.desc());
+        descs.extend(self.node_count.desc());
+        descs.extend(self.reloads.desc());
+        descs.extend(self.not_found_ratio.desc());
+        descs.extend(self.limiter_buckets.desc());
+        descs.extend(self.limiter_exhausted.desc());
+        descs.extend(self.limiter_rejected.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _lock = self.lock.lock();
+        self.update();
+        let mut families = Vec::new();
+        families.extend(self.build_epoch.collect());
+        families.extend(self.node_count.collect());
+        families.extend(self.reloads.collect());
+        families.extend(self.not_found_ratio.collect());
+        if self.limiter.is_some() {
+            families.extend(self.limiter_buckets.collect());
+            families.extend(self.limiter_exhausted.collect());
+            families.extend(self.limiter_rejected.collect());
+        }
+        families
+    }
+}
+
+/// The metrics of a server, rendered in the Prometheus text format.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+}
+
+impl Metrics {
+    pub fn new<T>(db: Arc<RwLock<Database<T>>>, limiter: Option<RateLimiter>) -> prometheus::Result<Metrics>
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let registry = Registry::new();
+        registry.register(Box::new(REQUESTS.clone()))?;
+        registry.register(Box::new(LATENCY.clone()))?;
+        registry.register(Box::new(CACHE_LOOKUPS.clone()))?;
+        registry.register(Box::new(DROPPED_SPANS.clone()))?;
+        registry.register(Box::new(DROPPED_ACCESS_LOG.clone()))?;
+        registry.register(Box::new(StateCollector::new(db, limiter)?))?;
+        Ok(Metrics { registry })
+    }
+
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            error!("failed to encode metrics, cause {}", err);
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+
+    /// Serves `GET /metrics` on `addr` from a background thread, answering each scraper from its own thread so
+    /// that a slow one does not hold up the others.
+    pub fn serve(self, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!("serving metrics on http://{}/metrics", listener.local_addr()?);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let metrics = self.clone();
+                let result = stream.map(|stream| {
+                    thread::spawn(move || {
+                        if let Err(err) = metrics.respond(stream) {
+                            error!("failed to serve metrics, cause {}", err);
+                        }
+                    })
+                });
+                if let Err(err) = result {
+                    error!("failed to serve metrics, cause {}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+
+        let mut parts = request_line.split_whitespace();
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", TEXT_FORMAT, self.render()),
+            _ => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_name() {
+        assert_eq!(code_name(RpcStatusCode::OK), "OK");
+        assert_eq!(code_name(RpcStatusCode::NOT_FOUND), "NOT_FOUND");
+        assert_eq!(code_name(RpcStatusCode::from(42)), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_serve_slow_scraper() {
+        let db: Arc<RwLock<Database<Vec<u8>>>> = Arc::new(RwLock::new(Database::empty(0)));
+        let metrics = Metrics::new(db, None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        metrics.serve(&addr.to_string()).unwrap();
+
+        // A scraper that connects and sends nothing.
+        let _slow = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut status = String::new();
+        BufReader::new(&stream).read_line(&mut status).unwrap();
+        assert_eq!(status, "HTTP/1.1 200 OK\r\n");
+    }
+
+    #[test]
+    fn test_not_found_ratio() {
+        let db: Arc<RwLock<Database<Vec<u8>>>> = Arc::new(RwLock::new(Database::empty(0)));
+        let collector = StateCollector::new(db, None).unwrap();
+        REQUESTS.with_label_values(&["lookup.Lookup/Lookup", "OK"]).inc_by(3);
+        REQUESTS.with_label_values(&["lookup.Lookup/Lookup", "NOT_FOUND"]).inc();
+        collector.update();
+        let ratio = collector
+            .not_found_ratio
+            .with_label_values(&["lookup.Lookup/Lookup"])
+            .get();
+        assert_eq!(ratio, 0.25);
+    }
+}
@@ -0,0 +1,390 @@
+use crate::auth::Tokens;
+use crate::metrics;
+use crate::proto::error_details::{QuotaFailure, QuotaFailure_Violation, RetryInfo};
+use crate::status;
+use grpcio::{CheckResult, RpcContext, RpcStatus, RpcStatusCode, ServerChecker};
+use protobuf::well_known_types::Duration as ProtoDuration;
+use protobuf::RepeatedField;
+use spin::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What the calls of a bucket have in common.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyBy {
+    /// The client address, without the port.
+    Peer,
+    /// The token name when `--token-file` is given, the client address otherwise.
+    Identity,
+}
+
+impl FromStr for KeyBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeyBy, String> {
+        match s {
+            "peer" => Ok(KeyBy::Peer),
+            "identity" => Ok(KeyBy::Identity),
+            _ => Err(format!("Unknown key '{}', expected peer or identity", s)),
+        }
+    }
+}
+
+/// A limit given as `method=rate[:burst]`.
+///
+/// The method is a full method such as `geoip2.GeoIp/Lookup`, a service such as `admin.Admin`
+/// or `*`, the rate is in calls per second and the burst defaults to the rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub method: String,
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl Rule {
+    /// How closely the rule matches `method`, `None` if it does not.
+    fn matches(&self, method: &str) -> Option<usize> {
+        let method = method.strip_prefix('/').unwrap_or(method);
+        if self.method == method {
+            Some(2)
+        } else if method
+            .split_once('/')
+            .is_some_and(|(service, _)| service == self.method)
+        {
+            Some(1)
+        } else if self.method == "*" {
+            Some(0)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let invalid = || format!("The limit must be 'method=rate[:burst]' but given '{}'", s);
+        let (method, limit) = s.split_once('=').ok_or_else(invalid)?;
+        let (rate, burst) = match limit.split_once(':') {
+            Some((rate, burst)) => (rate, Some(burst)),
+            None => (limit, None),
+        };
+        let rate: f64 = rate.parse().map_err(|_| invalid())?;
+        let burst: f64 = match burst {
+            Some(burst) => burst.parse().map_err(|_| invalid())?,
+            None => rate.max(1.0),
+        };
+        if method.is_empty() || !rate.is_finite() || rate <= 0.0 || !burst.is_finite() || burst < 1.0 {
+            return Err(invalid());
+        }
+        Ok(Rule {
+            method: method.to_string(),
+            rate,
+            burst,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(rule: &Rule, now: Instant) -> Bucket {
+        Bucket {
+            tokens: rule.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rule: &Rule, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rule.rate).min(rule.burst);
+        self.updated = now;
+    }
+
+    /// Takes a token, or tells how long until one is available.
+    fn take(&mut self, rule: &Rule, now: Instant) -> Result<(), Duration> {
+        self.refill(rule, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            // A tiny rate makes the wait overflow a duration.
+            Err(Duration::try_from_secs_f64((1.0 - self.tokens) / rule.rate).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+/// The tokens left in a bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BucketState {
+    pub method: String,
+    pub key: String,
+    pub tokens: f64,
+}
+
+/// Why a call was turned away.
+#[derive(Debug)]
+struct Rejection {
+    rule: Rule,
+    key: String,
+    retry_after: Duration,
+}
+
+/// Token buckets per rule and client, shared by every listener.
+#[derive(Clone)]
+pub struct RateLimiter {
+    rules: Arc<RwLock<Vec<Rule>>>,
+    key_by: KeyBy,
+    tokens: Option<Tokens>,
+    buckets: Arc<Mutex<HashMap<(usize, String), Bucket>>>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl RateLimiter {
+    pub fn new(rules: Vec<Rule>, key_by: KeyBy, tokens: Option<Tokens>) -> RateLimiter {
+        RateLimiter {
+            rules: Arc::new(RwLock::new(rules)),
+            key_by,
+            tokens,
+            buckets: Arc::default(),
+            rejected: Arc::default(),
+        }
+    }
+
+    /// Replaces the limits, starting every client with a full bucket.
+    pub fn set_rules(&self, rules: Vec<Rule>) {
+        let mut current = self.rules.write();
+        if *current != rules {
+            *current = rules;
+            self.buckets.lock().clear();
+        }
+    }
+
+    fn key(&self, ctx: &RpcContext<'_>) -> String {
+        if self.key_by == KeyBy::Identity {
+            if let Some(client) = self.tokens.as_ref().and_then(|tokens| tokens.authenticate(ctx)) {
+                return client.name;
+            }
+        }
+        peer_address(&ctx.peer()).to_string()
+    }
+
+    /// Takes a token from the bucket of the rule for `method` and the client `key` names, picking the rule under
+    /// the same lock so that the limits cannot be replaced in between.
+    fn take<K>(&self, method: &str, key: K, now: Instant) -> Result<(), Rejection>
+    where
+        K: FnOnce() -> String,
+    {
+        let rules = self.rules.read();
+        let i = match rule(&rules, method) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let key = key();
+        let mut buckets = self.buckets.lock();
+        buckets
+            .entry((i, key.clone()))
+            .or_insert_with(|| Bucket::full(&rules[i], now))
+            .take(&rules[i], now)
+            .map_err(|retry_after| Rejection {
+                rule: rules[i].clone(),
+                key,
+                retry_after,
+            })
+    }
+
+    /// Forgets the buckets that have refilled, as they behave like new ones.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let rules = self.rules.read();
+        self.buckets.lock().retain(|&(rule, _), bucket| {
+            let rule = &rules[rule];
+            bucket.refill(rule, now);
+            bucket.tokens < rule.burst
+        });
+    }
+
+    /// The buckets in use.
+    pub fn state(&self) -> Vec<BucketState> {
+        let now = Instant::now();
+        let rules = self.rules.read();
+        self.buckets
+            .lock()
+            .iter_mut()
+            .map(|(&(rule, ref key), bucket)| {
+                let rule = &rules[rule];
+                bucket.refill(rule, now);
+                BucketState {
+                    method: rule.method.clone(),
+                    key: key.clone(),
+                    tokens: bucket.tokens,
+                }
+            })
+            .collect()
+    }
+
+    /// The number of calls rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl ServerChecker for RateLimiter {
+    fn check(&mut self, ctx: &RpcContext) -> CheckResult {
+        let method = String::from_utf8_lossy(ctx.method());
+        match self.take(&method, || self.key(ctx), Instant::now()) {
+            Ok(()) => CheckResult::Continue,
+            Err(rejection) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                let status = exhausted(&rejection.rule, rejection.key, rejection.retry_after);
+                metrics::observe_rejected(ctx, &status);
+                CheckResult::Abort(status)
+            }
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn ServerChecker> {
+        Box::new(self.clone())
+    }
+}
+
+/// The index of the rule that most closely matches `method`, the first one among equals.
+fn rule(rules: &[Rule], method: &str) -> Option<usize> {
+    rules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rule)| rule.matches(method).map(|rank| (rank, i)))
+        .max_by_key(|&(rank, i)| (rank, std::cmp::Reverse(i)))
+        .map(|(_, i)| i)
+}
+
+/// Strips the port from addresses such as `ipv4:127.0.0.1:50312` or `ipv6:[::1]:50312`.
+fn peer_address(peer: &str) -> &str {
+    match peer.rsplit_once(':') {
+        Some((address, port)) if port.parse::<u16>().is_ok() => address,
+        _ => peer,
+    }
+}
+
+fn exhausted(rule: &Rule, key: String, retry_after: Duration) -> RpcStatus {
+    let message = format!(
+        "Rate limit of {}/s for {} exceeded, retry after {}ms",
+        rule.rate,
+        rule.method,
+        retry_after.as_millis().max(1)
+    );
+
+    let mut delay = ProtoDuration::new();
+    delay.set_seconds(retry_after.as_secs().min(i64::MAX as u64) as i64);
+    delay.set_nanos(retry_after.subsec_nanos() as i32);
+    let mut retry = RetryInfo::new();
+    retry.set_retry_delay(delay);
+
+    let mut violation = QuotaFailure_Violation::new();
+    violation.set_subject(key);
+    violation.set_description(format!("{} calls per second, burst of {}", rule.rate, rule.burst));
+    let mut quota = QuotaFailure::new();
+    quota.set_violations(RepeatedField::from_vec(vec![violation]));
+
+    status::with_details(
+        RpcStatusCode::RESOURCE_EXHAUSTED,
+        message,
+        vec![status::pack(&retry), status::pack(&quota)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            "geoip2.GeoIp/Lookup=50:100".parse(),
+            Ok(Rule {
+                method: "geoip2.GeoIp/Lookup".to_string(),
+                rate: 50.0,
+                burst: 100.0
+            })
+        );
+        assert_eq!("*=0.5".parse::<Rule>().map(|rule| rule.burst), Ok(1.0));
+        assert!("geoip2.GeoIp/Lookup".parse::<Rule>().is_err());
+        assert!("*=0".parse::<Rule>().is_err());
+        assert!("*=10:0".parse::<Rule>().is_err());
+        assert!("*=NaN".parse::<Rule>().is_err());
+        assert!("*=10:NaN".parse::<Rule>().is_err());
+        assert!("*=10:inf".parse::<Rule>().is_err());
+        assert_eq!("*=1e-320".parse::<Rule>().map(|rule| rule.burst), Ok(1.0));
+    }
+
+    #[test]
+    fn test_rule_for_method() {
+        let rules: Vec<Rule> = vec![
+            "*=100".parse().unwrap(),
+            "geoip2.GeoIp=50".parse().unwrap(),
+            "geoip2.GeoIp/Lookup=10".parse().unwrap(),
+        ];
+        assert_eq!(rule(&rules, "/geoip2.GeoIp/Lookup"), Some(2));
+        assert_eq!(rule(&rules, "/geoip2.GeoIp/Metadata"), Some(1));
+        assert_eq!(rule(&rules, "/admin.Admin/Reload"), Some(0));
+
+        let rules: Vec<Rule> = vec!["admin.Admin=1".parse().unwrap()];
+        assert_eq!(rule(&rules, "/geoip2.GeoIp/Lookup"), None);
+    }
+
+    #[test]
+    fn test_take() {
+        let limiter = RateLimiter::new(vec!["*=2:3".parse().unwrap()], KeyBy::Peer, None);
+        let take = |key: &str, now| {
+            limiter
+                .take("/geoip2.GeoIp/Lookup", || key.to_string(), now)
+                .map_err(|rejection| rejection.retry_after)
+        };
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(take("a", now), Ok(()));
+        }
+        assert_eq!(take("a", now), Err(Duration::from_millis(500)));
+        assert_eq!(take("b", now), Ok(()));
+        assert_eq!(take("a", now + Duration::from_millis(500)), Ok(()));
+
+        // The replaced limits apply from the next call, with the buckets of the new rules.
+        limiter.set_rules(vec!["admin.Admin=1".parse().unwrap(), "*=1:1".parse().unwrap()]);
+        assert_eq!(take("a", now), Ok(()));
+        let rejection = limiter
+            .take("/geoip2.GeoIp/Lookup", || "a".to_string(), now)
+            .unwrap_err();
+        assert_eq!(rejection.rule.method, "*");
+        assert_eq!(rejection.key, "a");
+    }
+
+    #[test]
+    fn test_take_tiny_rate() {
+        let limiter = RateLimiter::new(vec!["*=1e-320".parse().unwrap()], KeyBy::Peer, None);
+        let now = Instant::now();
+        assert!(limiter.take("/geoip2.GeoIp/Lookup", || "a".to_string(), now).is_ok());
+        let rejection = limiter
+            .take("/geoip2.GeoIp/Lookup", || "a".to_string(), now)
+            .unwrap_err();
+        assert_eq!(rejection.retry_after, Duration::MAX);
+        assert_eq!(
+            exhausted(&rejection.rule, rejection.key, rejection.retry_after).code(),
+            RpcStatusCode::RESOURCE_EXHAUSTED
+        );
+    }
+
+    #[test]
+    fn test_peer_address() {
+        assert_eq!(peer_address("ipv4:127.0.0.1:50312"), "ipv4:127.0.0.1");
+        assert_eq!(peer_address("ipv6:[::1]:50312"), "ipv6:[::1]");
+        assert_eq!(peer_address("unix:/run/mmdb.sock"), "unix:/run/mmdb.sock");
+    }
+}
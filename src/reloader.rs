@@ -3,8 +3,10 @@ use clap::Parser;
 use cron::Schedule;
 use grpcio::{ChannelBuilder, EnvBuilder, Error};
 use log::{debug, info};
+use mmdb_grpc::proto::admin_grpc::AdminClient;
 use mmdb_grpc::proto::geoip2::*;
 use mmdb_grpc::proto::geoip2_grpc::GeoIpClient;
+use mmdb_grpc::{auth, tls};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::thread;
@@ -17,14 +19,31 @@ struct Opts {
     host: String,
     #[clap(short = 'P', long = "port", value_parser, default_value = "50000")]
     port: u16,
+    #[clap(long = "tls", value_parser)]
+    tls: bool,
+    #[clap(long = "tls-ca", value_parser)]
+    tls_ca: Option<String>,
+    #[clap(long = "tls-cert", value_parser, requires = "tls_key")]
+    tls_cert: Option<String>,
+    #[clap(long = "tls-key", value_parser, requires = "tls_cert")]
+    tls_key: Option<String>,
+    #[clap(long = "tls-server-name", value_parser)]
+    tls_server_name: Option<String>,
+    #[clap(long = "token", value_parser)]
+    token: Option<String>,
     #[clap(short = 'S', long = "schedule", value_parser)]
     schedule: Option<String>,
+    #[clap(long = "geoip-reload", value_parser)]
+    geoip_reload: bool,
 }
 
 impl Opts {
     fn host(&self) -> &String {
         &self.host
     }
+    fn tls(&self) -> bool {
+        self.tls || self.tls_ca.is_some() || self.tls_cert.is_some()
+    }
 }
 
 fn main() -> Result<(), Error> {
@@ -33,8 +52,28 @@ fn main() -> Result<(), Error> {
     let opts = Opts::parse();
 
     let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(format!("{}:{}", opts.host(), opts.port).as_ref());
-    let client = GeoIpClient::new(ch);
+    let addr = format!("{}:{}", opts.host(), opts.port);
+    let mut builder = ChannelBuilder::new(env);
+    if let Some(ref name) = opts.tls_server_name {
+        builder = builder.override_ssl_target(name.as_str());
+    }
+    let ch = if opts.tls() {
+        let creds =
+            tls::channel_credentials(opts.tls_ca.as_ref(), opts.tls_cert.as_ref(), opts.tls_key.as_ref()).unwrap();
+        builder.secure_connect(addr.as_str(), creds)
+    } else {
+        builder.connect(addr.as_str())
+    };
+    let geoip = GeoIpClient::new(ch.clone());
+    let admin = AdminClient::new(ch);
+    let reload = || {
+        let opt = auth::call_option(opts.token.as_deref());
+        if opts.geoip_reload {
+            geoip.reload_opt(&Empty::new(), opt)
+        } else {
+            admin.reload_opt(&Empty::new(), opt)
+        }
+    };
 
     if let Some(ref expr) = opts.schedule {
         let schedule = Schedule::from_str(expr).unwrap();
@@ -51,14 +90,10 @@ fn main() -> Result<(), Error> {
 
             thread::sleep(delay);
 
-            client
-                .reload(&Empty::new())
-                .map(|r| info!("succeeded to reload: {:?}", r))?;
+            reload().map(|r| info!("succeeded to reload: {:?}", r))?;
         }
     } else {
-        client
-            .reload(&Empty::new())
-            .map(|r| info!("succeeded to reload: {:?}", r))?;
+        reload().map(|r| info!("succeeded to reload: {:?}", r))?;
     }
 
     Ok(())
@@ -0,0 +1,143 @@
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A block of the IANA special-purpose address registries, which no geolocation database maps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reserved {
+    pub network: IpAddr,
+    pub prefix_len: u8,
+    /// The name of the registry entry, e.g. `Private-Use`.
+    pub name: &'static str,
+    pub rfc: &'static str,
+}
+
+impl Display for Reserved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+const fn v4(a: u8, b: u8, c: u8, d: u8, prefix_len: u8, name: &'static str, rfc: &'static str) -> Reserved {
+    Reserved {
+        network: IpAddr::V4(Ipv4Addr::new(a, b, c, d)),
+        prefix_len,
+        name,
+        rfc,
+    }
+}
+
+const fn v6(segments: [u16; 8], prefix_len: u8, name: &'static str, rfc: &'static str) -> Reserved {
+    let [a, b, c, d, e, f, g, h] = segments;
+    Reserved {
+        network: IpAddr::V6(Ipv6Addr::new(a, b, c, d, e, f, g, h)),
+        prefix_len,
+        name,
+        rfc,
+    }
+}
+
+const RESERVED: [Reserved; 25] = [
+    v4(0, 0, 0, 0, 8, "This network", "RFC 791"),
+    v4(10, 0, 0, 0, 8, "Private-Use", "RFC 1918"),
+    v4(100, 64, 0, 0, 10, "Shared Address Space", "RFC 6598"),
+    v4(127, 0, 0, 0, 8, "Loopback", "RFC 1122"),
+    v4(169, 254, 0, 0, 16, "Link Local", "RFC 3927"),
+    v4(172, 16, 0, 0, 12, "Private-Use", "RFC 1918"),
+    v4(192, 0, 0, 0, 24, "IETF Protocol Assignments", "RFC 6890"),
+    v4(192, 0, 2, 0, 24, "Documentation (TEST-NET-1)", "RFC 5737"),
+    v4(192, 88, 99, 0, 24, "Deprecated (6to4 Relay Anycast)", "RFC 7526"),
+    v4(192, 168, 0, 0, 16, "Private-Use", "RFC 1918"),
+    v4(198, 18, 0, 0, 15, "Benchmarking", "RFC 2544"),
+    v4(198, 51, 100, 0, 24, "Documentation (TEST-NET-2)", "RFC 5737"),
+    v4(203, 0, 113, 0, 24, "Documentation (TEST-NET-3)", "RFC 5737"),
+    v4(224, 0, 0, 0, 4, "Multicast", "RFC 5771"),
+    v4(240, 0, 0, 0, 4, "Reserved", "RFC 1112"),
+    v4(255, 255, 255, 255, 32, "Limited Broadcast", "RFC 919"),
+    v6([0, 0, 0, 0, 0, 0, 0, 0], 128, "Unspecified Address", "RFC 4291"),
+    v6([0, 0, 0, 0, 0, 0, 0, 1], 128, "Loopback Address", "RFC 4291"),
+    v6(
+        [0x64, 0xff9b, 0, 0, 0, 0, 0, 0],
+        96,
+        "IPv4-IPv6 Translation",
+        "RFC 6052",
+    ),
+    v6(
+        [0x100, 0, 0, 0, 0, 0, 0, 0],
+        64,
+        "Discard-Only Address Block",
+        "RFC 6666",
+    ),
+    v6(
+        [0x2001, 0, 0, 0, 0, 0, 0, 0],
+        23,
+        "IETF Protocol Assignments",
+        "RFC 2928",
+    ),
+    v6([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32, "Documentation", "RFC 3849"),
+    v6([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7, "Unique-Local", "RFC 4193"),
+    v6([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10, "Link-Local Unicast", "RFC 4291"),
+    v6([0xff00, 0, 0, 0, 0, 0, 0, 0], 8, "Multicast", "RFC 4291"),
+];
+
+/// The address bits, left aligned so that IPv4 and IPv6 prefixes compare alike.
+fn bits(ip: IpAddr) -> (u128, bool) {
+    match ip {
+        IpAddr::V4(v4) => ((u32::from(v4) as u128) << 96, false),
+        IpAddr::V6(v6) => (u128::from(v6), true),
+    }
+}
+
+impl Reserved {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, network_v6) = bits(self.network);
+        let (ip, ip_v6) = bits(ip);
+        let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+        network_v6 == ip_v6 && network & mask == ip & mask
+    }
+}
+
+/// The most specific special-purpose block containing `ip`, if any. An IPv4-mapped IPv6 address such as
+/// `::ffff:10.0.0.1` is classified by the IPv4 address it embeds, as the databases map it to that address too.
+pub fn classify(ip: IpAddr) -> Option<&'static Reserved> {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    };
+    RESERVED
+        .iter()
+        .filter(|reserved| reserved.contains(ip))
+        .max_by_key(|reserved| reserved.prefix_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(ip: &str) -> Option<&'static str> {
+        classify(ip.parse().unwrap()).map(|reserved| reserved.name)
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(name("10.1.2.3"), Some("Private-Use"));
+        assert_eq!(name("172.31.255.255"), Some("Private-Use"));
+        assert_eq!(name("172.32.0.0"), None);
+        assert_eq!(name("100.64.0.1"), Some("Shared Address Space"));
+        assert_eq!(name("192.0.2.1"), Some("Documentation (TEST-NET-1)"));
+        assert_eq!(name("255.255.255.255"), Some("Limited Broadcast"));
+        assert_eq!(name("8.8.8.8"), None);
+        assert_eq!(name("::1"), Some("Loopback Address"));
+        assert_eq!(name("fd12:3456::1"), Some("Unique-Local"));
+        assert_eq!(name("2001:db8::1"), Some("Documentation"));
+        assert_eq!(name("2001:4860:4860::8888"), None);
+        assert_eq!(name("::ffff:10.0.0.1"), Some("Private-Use"));
+        assert_eq!(name("::ffff:8.8.8.8"), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let reserved = classify("192.168.1.1".parse().unwrap()).unwrap();
+        assert_eq!(reserved.to_string(), "192.168.0.0/16");
+        assert_eq!(reserved.rfc, "RFC 1918");
+    }
+}
@@ -1,45 +1,217 @@
 use clap::Parser;
-use crossbeam_channel::{bounded, select, Receiver};
+use crossbeam_channel::{bounded, never, select, tick, Receiver};
 use futures::executor::block_on;
-use grpcio::{ChannelBuilder, Environment, ServerBuilder, ServerCredentials};
+use grpcio::{ChannelArgs, ChannelBuilder, Environment, Server, ServerBuilder, ServerCredentials};
 use grpcio_health::proto::*;
-use log::{error, info};
-use maxminddb as mmdb;
-use mmdb_grpc::proto::geoip2_grpc;
-use mmdb_grpc::{CityService, HealthService};
+use log::{error, info, warn};
+use maxminddb::MaxMindDBError;
+use mmdb_grpc::access::{self, Destination, Rotation};
+use mmdb_grpc::admin::{self, AdminService};
+use mmdb_grpc::auth::{AuthChecker, Tokens};
+use mmdb_grpc::cache::ReplyCache;
+use mmdb_grpc::config;
+use mmdb_grpc::database::{Database, Version};
+use mmdb_grpc::health::HealthReporter;
+use mmdb_grpc::listen::{self, Address, Listen, Owner, Security, Services};
+use mmdb_grpc::metrics::Metrics;
+use mmdb_grpc::proto::admin_grpc;
+use mmdb_grpc::ratelimit::{KeyBy, RateLimiter, Rule};
+use mmdb_grpc::tls::{self, CertificateReloader, TlsFiles};
+use mmdb_grpc::trace;
+use mmdb_grpc::updater::{self, Updater};
+use mmdb_grpc::{create_geo_ip, create_lookup, CityService};
 use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
 use signal_hook::iterator::Signals;
 use spin::RwLock;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, PartialEq)]
 #[clap(author, version, about, long_about = None)]
 struct Opts {
-    #[clap(short = 'H', long = "host", value_parser, default_value = "localhost")]
+    #[clap(long = "config", env = "MMDB_CONFIG", value_parser)]
+    config: Option<String>,
+    #[clap(
+        short = 'H',
+        long = "host",
+        env = "MMDB_HOST",
+        value_parser,
+        default_value = "localhost"
+    )]
     host: String,
-    #[clap(short = 'P', long = "port", value_parser, default_value = "50000")]
+    #[clap(short = 'P', long = "port", env = "MMDB_PORT", value_parser, default_value = "50000")]
     port: u16,
     #[clap(
         short = 'F',
         long = "file",
+        env = "MMDB_FILE",
         value_parser,
         default_value = "/usr/share/GeoIP/GeoLite2-City.mmdb"
     )]
     mmdb_path: String,
-    #[clap(short = 'W', long = "workers", value_parser, default_value = "1")]
+    #[clap(long = "wait-for-database", env = "MMDB_WAIT_FOR_DATABASE", value_parser)]
+    wait_for_database: bool,
+    #[clap(
+        short = 'W',
+        long = "workers",
+        env = "MMDB_WORKERS",
+        value_parser,
+        default_value = "1"
+    )]
     workers: usize,
-    #[clap(long = "slots-per-worker", value_parser)]
+    #[clap(long = "slots-per-worker", env = "MMDB_SLOTS_PER_WORKER", value_parser)]
     slots_per_worker: Option<usize>,
-    #[clap(long = "keepalive-time", value_parser)]
-    keepalive_time: Option<String>,
-    #[clap(long = "keepalive-timeout", value_parser)]
-    keepalive_timeout: Option<String>,
-    #[clap(long = "keepalive-permit-without-calls", value_parser)]
+    #[clap(long = "keepalive-time", env = "MMDB_KEEPALIVE_TIME", value_parser = duration)]
+    keepalive_time: Option<Duration>,
+    #[clap(long = "keepalive-timeout", env = "MMDB_KEEPALIVE_TIMEOUT", value_parser = duration)]
+    keepalive_timeout: Option<Duration>,
+    #[clap(
+        long = "keepalive-permit-without-calls",
+        env = "MMDB_KEEPALIVE_PERMIT_WITHOUT_CALLS",
+        value_parser
+    )]
     keepalive_permit_without_calls: Option<bool>,
+    #[clap(
+        long = "keep-versions",
+        env = "MMDB_KEEP_VERSIONS",
+        value_parser,
+        default_value = "0"
+    )]
+    keep_versions: usize,
+    #[clap(long = "update-endpoint", env = "MMDB_UPDATE_ENDPOINT", value_parser, default_value = updater::DEFAULT_ENDPOINT)]
+    update_endpoint: String,
+    #[clap(long = "account-id", env = "MMDB_ACCOUNT_ID", value_parser)]
+    account_id: Option<String>,
+    #[clap(
+        long = "license-key",
+        env = "MMDB_LICENSE_KEY",
+        value_parser,
+        requires = "account_id"
+    )]
+    license_key: Option<String>,
+    #[clap(
+        long = "edition-id",
+        env = "MMDB_EDITION_ID",
+        value_parser,
+        default_value = "GeoLite2-City"
+    )]
+    edition_id: String,
+    #[clap(
+        long = "update-interval",
+        env = "MMDB_UPDATE_INTERVAL",
+        value_parser = duration,
+        default_value = "24h"
+    )]
+    update_interval: Duration,
+    #[clap(long = "max-database-age", env = "MMDB_MAX_DATABASE_AGE", value_parser = duration)]
+    max_database_age: Option<Duration>,
+    #[clap(long = "drain-period", env = "MMDB_DRAIN_PERIOD", value_parser = duration, default_value = "0s")]
+    drain_period: Duration,
+    #[clap(
+        long = "shutdown-timeout",
+        env = "MMDB_SHUTDOWN_TIMEOUT",
+        value_parser = duration,
+        default_value = "30s"
+    )]
+    shutdown_timeout: Duration,
+    #[clap(long = "tls-cert", env = "MMDB_TLS_CERT", value_parser, requires = "tls_key")]
+    tls_cert: Option<String>,
+    #[clap(long = "tls-key", env = "MMDB_TLS_KEY", value_parser, requires = "tls_cert")]
+    tls_key: Option<String>,
+    #[clap(
+        long = "tls-client-ca",
+        env = "MMDB_TLS_CLIENT_CA",
+        value_parser,
+        requires = "tls_cert"
+    )]
+    tls_client_ca: Option<String>,
+    #[clap(long = "listen", env = "MMDB_LISTEN", value_parser, value_delimiter = ';')]
+    listen: Vec<Listen>,
+    #[clap(long = "admin-listen", env = "MMDB_ADMIN_LISTEN", value_parser)]
+    admin_listen: Option<Listen>,
+    #[clap(long = "socket-mode", env = "MMDB_SOCKET_MODE", value_parser = listen::parse_mode)]
+    socket_mode: Option<u32>,
+    #[clap(long = "socket-owner", env = "MMDB_SOCKET_OWNER", value_parser)]
+    socket_owner: Option<Owner>,
+    #[clap(long = "geoip-reload", env = "MMDB_GEOIP_RELOAD", value_parser)]
+    geoip_reload: bool,
+    #[clap(long = "empty-if-not-found", env = "MMDB_EMPTY_IF_NOT_FOUND", value_parser)]
+    empty_if_not_found: bool,
+    #[clap(long = "cache-size", env = "MMDB_CACHE_SIZE", value_parser, default_value = "0")]
+    cache_size: usize,
+    #[clap(long = "token-file", env = "MMDB_TOKEN_FILE", value_parser)]
+    token_file: Option<String>,
+    #[clap(long = "rate-limit", env = "MMDB_RATE_LIMIT", value_parser, value_delimiter = ';')]
+    rate_limit: Vec<Rule>,
+    #[clap(
+        long = "rate-limit-key",
+        env = "MMDB_RATE_LIMIT_KEY",
+        value_parser,
+        default_value = "peer"
+    )]
+    rate_limit_key: KeyBy,
+    #[clap(long = "metrics-listen", env = "MMDB_METRICS_LISTEN", value_parser)]
+    metrics_listen: Option<String>,
+    #[clap(long = "otlp-endpoint", env = "MMDB_OTLP_ENDPOINT", value_parser)]
+    otlp_endpoint: Option<String>,
+    #[clap(
+        long = "otlp-service-name",
+        env = "MMDB_OTLP_SERVICE_NAME",
+        value_parser,
+        default_value = "mmdb-server"
+    )]
+    otlp_service_name: String,
+    #[clap(
+        long = "trace-sample-ratio",
+        env = "MMDB_TRACE_SAMPLE_RATIO",
+        value_parser,
+        default_value = "1"
+    )]
+    trace_sample_ratio: f64,
+    #[clap(long = "access-log", env = "MMDB_ACCESS_LOG", value_parser)]
+    access_log: Option<String>,
+    #[clap(long = "access-log-anonymize", env = "MMDB_ACCESS_LOG_ANONYMIZE", value_parser)]
+    access_log_anonymize: bool,
+    #[clap(long = "access-log-max-size", env = "MMDB_ACCESS_LOG_MAX_SIZE", value_parser = access::parse_size)]
+    access_log_max_size: Option<u64>,
+    #[clap(
+        long = "access-log-max-files",
+        env = "MMDB_ACCESS_LOG_MAX_FILES",
+        value_parser,
+        default_value = "5"
+    )]
+    access_log_max_files: usize,
 }
 
 impl Opts {
+    /// Falls back to the host and port when no `--listen` is given, followed by the `--admin-listen`
+    /// listener serving the admin service only.
+    fn listen(&self) -> Vec<Listen> {
+        let mut listens = if self.listen.is_empty() {
+            vec![Listen::new(Address::Tcp(format!(
+                "{}:{}",
+                self.host().as_str(),
+                self.port
+            )))]
+        } else {
+            self.listen.clone()
+        };
+        if let Some(ref admin) = self.admin_listen {
+            listens.push(Listen {
+                services: Services::admin(),
+                ..admin.clone()
+            });
+        }
+        listens
+    }
     fn mmdb_path(&self) -> &String {
         &self.mmdb_path
     }
@@ -48,77 +220,373 @@ impl Opts {
     }
 }
 
+/// Why the server could not start, each kind exiting with its own code so that a supervisor can
+/// tell a misconfiguration, which needs a fix, from a failure that may go away on retry.
+#[derive(Debug)]
+enum StartupError {
+    /// Options that cannot work together.
+    Invalid(String),
+    Database(String, MaxMindDBError),
+    /// A file other than the database, named by what it is for.
+    File(&'static str, String, io::Error),
+    Bind(String, String),
+    Internal(String),
+}
+
+impl StartupError {
+    // The codes follow sysexits.h.
+    fn exit_code(&self) -> i32 {
+        match self {
+            StartupError::Invalid(_) => 78,
+            StartupError::Database(_, MaxMindDBError::IoError(_)) => 66,
+            StartupError::Database(_, _) => 65,
+            StartupError::File(_, _, err) => match err.kind() {
+                io::ErrorKind::NotFound => 66,
+                io::ErrorKind::PermissionDenied => 77,
+                io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => 65,
+                _ => 74,
+            },
+            StartupError::Bind(_, _) => 75,
+            StartupError::Internal(_) => 70,
+        }
+    }
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupError::Invalid(msg) => write!(f, "invalid options: {}", msg),
+            StartupError::Database(path, MaxMindDBError::IoError(err)) => {
+                write!(f, "failed to read the database {}, check --file: {}", path, err)
+            }
+            StartupError::Database(path, err) => write!(f, "{} is not a valid MaxMind database: {}", path, err),
+            StartupError::File(what, path, err) => write!(f, "failed to load the {} {}: {}", what, path, err),
+            StartupError::Bind(addr, err) => write!(f, "failed to listen on {}, is it in use? {}", addr, err),
+            StartupError::Internal(msg) => write!(f, "failed to start the server: {}", msg),
+        }
+    }
+}
+
+fn duration(s: &str) -> Result<Duration, String> {
+    parse_duration::parse(s).map_err(|_| format!("The duration must be like 30s or 1h but given '{}'", s))
+}
+
+/// Checks the options that clap cannot check one by one.
+fn validate(opts: &Opts) -> Result<(), StartupError> {
+    let invalid = |msg: &str| Err(StartupError::Invalid(msg.to_string()));
+    if opts.workers == 0 {
+        return invalid("--workers must be at least 1");
+    }
+    if opts.account_id.is_some() && opts.license_key.is_none() {
+        return invalid("--account-id needs --license-key to download updates");
+    }
+    if opts.update_interval.is_zero() {
+        return invalid("--update-interval must be longer than 0s");
+    }
+    if opts.rate_limit_key == KeyBy::Identity && opts.token_file.is_none() {
+        return invalid("--rate-limit-key identity needs --token-file to identify clients");
+    }
+    if !(0.0..=1.0).contains(&opts.trace_sample_ratio) {
+        return invalid("--trace-sample-ratio must be between 0 and 1");
+    }
+    let listens = opts.listen();
+    for (i, listen) in listens.iter().enumerate() {
+        if listens[..i].iter().any(|other| other.address == listen.address) {
+            return Err(StartupError::Invalid(format!(
+                "{} is given to --listen more than once",
+                listen
+            )));
+        }
+    }
+    Ok(())
+}
+
 fn main() {
     env_logger::init();
 
-    let opts = Opts::parse();
-    let addr = format!("{}:{}", opts.host().as_str(), opts.port);
+    let opts: Opts = config::parse(env::args_os()).unwrap_or_else(|err| err.exit());
+    if let Err(err) = run(opts) {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn run(opts: Opts) -> Result<(), StartupError> {
+    validate(&opts)?;
+
+    if let Some(ref endpoint) = opts.otlp_endpoint {
+        trace::init(endpoint, &opts.otlp_service_name, opts.trace_sample_ratio);
+    }
+    if let Some(ref destination) = opts.access_log {
+        let rotation = opts.access_log_max_size.map(|max_size| Rotation {
+            max_size,
+            max_files: opts.access_log_max_files,
+        });
+        access::init(
+            &Destination::from(destination.as_str()),
+            rotation,
+            opts.access_log_anonymize,
+        )
+        .map_err(|err| StartupError::File("access log", destination.clone(), err))?;
+    }
 
-    let reader = mmdb::Reader::open_readfile(opts.mmdb_path()).unwrap();
-    let mmdb = Arc::new(RwLock::new(reader));
+    let database = if opts.wait_for_database && !Path::new(opts.mmdb_path()).exists() {
+        warn!("{} does not exist yet, serving once it appears", opts.mmdb_path());
+        Database::empty(opts.keep_versions)
+    } else {
+        let version =
+            Version::open(opts.mmdb_path()).map_err(|err| StartupError::Database(opts.mmdb_path().clone(), err))?;
+        Database::new(version, opts.keep_versions)
+    };
+    let mmdb = Arc::new(RwLock::new(database));
 
     let env = Arc::new(Environment::new(opts.workers));
     let cloned_path = opts.mmdb_path().clone();
-    let geoip_service = geoip2_grpc::create_geo_ip(CityService::new(mmdb.clone(), move || {
-        mmdb::Reader::open_readfile(cloned_path.clone())
-    }));
-    let health_service = create_health(HealthService);
+    let reloader = move || Version::open(&cloned_path);
+    let city_service = CityService::new(
+        mmdb.clone(),
+        opts.geoip_reload.then(|| reloader.clone()),
+        opts.empty_if_not_found,
+        NonZeroUsize::new(opts.cache_size).map(ReplyCache::new),
+    );
+    let admin_service = AdminService::new(mmdb.clone(), reloader);
+    let mut health = HealthReporter::new(mmdb.clone(), opts.max_database_age);
+
+    let tls_files = match (&opts.tls_cert, &opts.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsFiles {
+            cert: PathBuf::from(cert),
+            key: PathBuf::from(key),
+            client_ca: opts.tls_client_ca.as_ref().map(PathBuf::from),
+        }),
+        _ => None,
+    };
+    let certificates = CertificateReloader::default();
+    let tokens = match opts.token_file {
+        Some(ref path) => Some(Tokens::open(path).map_err(|err| StartupError::File("token file", path.clone(), err))?),
+        None => None,
+    };
+    let limiter = (!opts.rate_limit.is_empty())
+        .then(|| RateLimiter::new(opts.rate_limit.clone(), opts.rate_limit_key, tokens.clone()));
+    if let Some(ref addr) = opts.metrics_listen {
+        let metrics =
+            Metrics::new(mmdb.clone(), limiter.clone()).map_err(|err| StartupError::Internal(err.to_string()))?;
+        metrics
+            .serve(addr)
+            .map_err(|err| StartupError::Bind(addr.clone(), err.to_string()))?;
+    }
+
+    let listens = opts.listen();
+    let mut servers = Vec::with_capacity(listens.len());
+    for listen in listens.iter() {
+        let mut builder = ServerBuilder::new(env.clone()).channel_args(channel_args(&env, &opts));
+        if listen.services.geoip {
+            builder = builder.register_service(create_geo_ip(city_service.clone()));
+            builder = builder.register_service(create_lookup(city_service.clone()));
+        }
+        if listen.services.admin {
+            builder = builder.register_service(admin_grpc::create_admin(admin_service.clone()));
+        }
+        if listen.services.health {
+            builder = builder.register_service(create_health(health.service()));
+        }
+        if let Some(v) = opts.slots_per_worker {
+            builder = builder.requests_slot_per_cq(v);
+        }
+        // Limits apply before authentication, so that a flood of calls with unknown tokens is rejected by
+        // peer before their tokens are hashed.
+        if let Some(ref limiter) = limiter {
+            builder = builder.add_checker(limiter.clone());
+        }
+        if let Some(ref tokens) = tokens {
+            builder = builder.add_checker(AuthChecker::new(tokens.clone()));
+        }
+
+        // The peer of a unix socket is on the same host, so the server wide TLS only applies to TCP.
+        let creds = match (&listen.security, &listen.address, &tls_files) {
+            (Security::Tls(files), _, _) | (Security::Default, Address::Tcp(_), Some(files)) => {
+                tls::server_credentials(files, &certificates)
+                    .map_err(|err| StartupError::File(err.what, err.path.display().to_string(), err.source))?
+            }
+            _ => ServerCredentials::insecure(),
+        };
+
+        let mut server = builder.build().map_err(|err| StartupError::Internal(err.to_string()))?;
+        server
+            .add_listening_port(&listen.to_string(), creds)
+            .map_err(|err| StartupError::Bind(listen.to_string(), err.to_string()))?;
+        if let Some(path) = listen.address.socket_path() {
+            listen::set_permissions(path, opts.socket_mode, opts.socket_owner)
+                .map_err(|err| StartupError::File("socket", path.display().to_string(), err))?;
+        }
+        servers.push(server);
+    }
 
+    let mmdb_path = opts.mmdb_path();
+    let term_event = terminate_channel().map_err(StartupError::Internal)?;
+    let reload_event = reload_channel().map_err(StartupError::Internal)?;
+    for (server, listen) in servers.iter_mut().zip(listens.iter()) {
+        server.start();
+        info!("started mmdb-grpc server listening on {}", listen);
+    }
+    health.refresh();
+
+    let update_event = match (&opts.account_id, &opts.license_key) {
+        (Some(account_id), Some(license_key)) => {
+            let updater = Updater::new(
+                &opts.update_endpoint,
+                account_id,
+                license_key,
+                &opts.edition_id,
+                mmdb_path,
+            );
+            update_channel(updater, opts.update_interval)
+        }
+        _ => never(),
+    };
+    let health_event = tick(Duration::from_secs(1));
+    let mut attempted = None;
+    loop {
+        select! {
+            recv(reload_event) -> _ => {
+                reload(&mmdb, mmdb_path);
+                certificates.reload();
+                if let Some(ref tokens) = tokens {
+                    tokens.reload();
+                }
+                if opts.config.is_some() {
+                    reconfigure(&opts, &mut health, limiter.as_ref());
+                }
+            }
+            recv(update_event) -> _ => reload(&mmdb, mmdb_path),
+            recv(health_event) -> _ => {
+                if opts.wait_for_database {
+                    load_appeared(&mmdb, mmdb_path, &mut attempted);
+                }
+                health.refresh();
+                if let Some(ref limiter) = limiter {
+                    limiter.prune();
+                }
+            }
+            recv(term_event) -> _ => break,
+        }
+    }
+
+    health.shutdown();
+    if !opts.drain_period.is_zero() {
+        info!("draining for {:?}", opts.drain_period);
+        thread::sleep(opts.drain_period);
+    }
+
+    shutdown(&mut servers, opts.shutdown_timeout);
+    for path in listens.iter().filter_map(|listen| listen.address.socket_path()) {
+        let _ = fs::remove_file(path);
+    }
+    access::flush(Duration::from_secs(5));
+    trace::flush(Duration::from_secs(5));
+    info!("bye!");
+    Ok(())
+}
+
+fn channel_args(env: &Arc<Environment>, opts: &Opts) -> ChannelArgs {
     let mut channel_builder = ChannelBuilder::new(env.clone());
-    if let Some(ref v) = opts.keepalive_time {
-        let t = parse_duration::parse(v.as_str()).unwrap();
+    if let Some(t) = opts.keepalive_time {
         channel_builder = channel_builder.keepalive_time(t);
     }
-    if let Some(ref v) = opts.keepalive_timeout {
-        let t = parse_duration::parse(v.as_str()).unwrap();
+    if let Some(t) = opts.keepalive_timeout {
         channel_builder = channel_builder.keepalive_timeout(t);
     }
     if let Some(v) = opts.keepalive_permit_without_calls {
         channel_builder = channel_builder.keepalive_permit_without_calls(v)
     }
+    channel_builder.build_args()
+}
 
-    let mut builder = ServerBuilder::new(env)
-        .register_service(geoip_service)
-        .register_service(health_service)
-        .channel_args(channel_builder.build_args());
+/// How long the handlers of cancelled calls are given to return.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
 
-    if let Some(v) = opts.slots_per_worker {
-        builder = builder.requests_slot_per_cq(v);
+/// Stops accepting calls and waits for the in-flight ones, cancelling them after `timeout`.
+fn shutdown(servers: &mut [Server], timeout: Duration) {
+    let (sender, receiver) = bounded(servers.len());
+    for server in servers.iter_mut() {
+        let f = server.shutdown();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send(block_on(f));
+        });
     }
 
-    let mut server = builder.build().unwrap();
-    server
-        .add_listening_port(addr.as_str(), ServerCredentials::insecure())
-        .unwrap();
-    server.start();
+    let mut pending = servers.len();
+    let deadline = Instant::now() + timeout;
+    while pending > 0 && receiver.recv_deadline(deadline).is_ok() {
+        pending -= 1;
+    }
+    if pending == 0 {
+        return;
+    }
 
-    info!("started mmdb-grpc server listening on {}", addr);
+    warn!("in-flight calls did not finish within {:?}, cancelling them", timeout);
+    for server in servers.iter_mut() {
+        server.cancel_all_calls();
+    }
+    let deadline = Instant::now() + CANCEL_TIMEOUT;
+    while pending > 0 && receiver.recv_deadline(deadline).is_ok() {
+        pending -= 1;
+    }
+    if pending > 0 {
+        error!(
+            "{} servers did not stop within {:?} of cancelling their calls",
+            pending, CANCEL_TIMEOUT
+        );
+    }
+}
 
-    let mmdb_path = opts.mmdb_path();
-    let term_event = terminate_channel().unwrap();
-    let reload_event = reload_channel().unwrap();
-    loop {
-        select! {
-            recv(reload_event) -> _ => {
-                match mmdb::Reader::open_readfile(mmdb_path.clone()) {
-                    Ok(reader) => {
-                        let mut db = (*mmdb).write();
-                        *db = reader;
-                        info!("succeeded to reload mmdb");
-                    }
-                    Err(err) => {
-                        error!("failed to reload mmdb, cause {:?}", err);
-                    }
-                }
-            }
-            recv(term_event) -> _ => {
-                info!("bye!");
-                break;
-            }
+/// Reads the config file again and applies what can change without a restart:
+/// `max-database-age` and `rate-limit`.
+fn reconfigure(opts: &Opts, health: &mut HealthReporter<Arc<[u8]>>, limiter: Option<&RateLimiter>) {
+    let reloaded: Opts = match config::parse(env::args_os()) {
+        Ok(reloaded) => reloaded,
+        Err(err) => {
+            error!("failed to reload the config, cause {}", err);
+            return;
         }
+    };
+
+    health.set_max_age(reloaded.max_database_age);
+    match limiter {
+        Some(limiter) => limiter.set_rules(reloaded.rate_limit.clone()),
+        None if !reloaded.rate_limit.is_empty() => warn!("rate limits take effect after a restart"),
+        None => {}
     }
 
-    let _ = block_on(server.shutdown());
+    let restart = Opts {
+        max_database_age: opts.max_database_age,
+        rate_limit: opts.rate_limit.clone(),
+        ..reloaded
+    };
+    if restart != *opts {
+        warn!("some of the changed options take effect after a restart");
+    }
+    info!("reloaded the config");
+}
+
+fn reload(mmdb: &RwLock<Database<Arc<[u8]>>>, mmdb_path: &str) {
+    let _ = admin::reload(mmdb, &|| Version::open(mmdb_path));
+}
+
+/// Loads the database once its file appears, trying again after a failure only when the file
+/// has been modified since, e.g. when it was still being written.
+fn load_appeared(mmdb: &RwLock<Database<Arc<[u8]>>>, mmdb_path: &str, attempted: &mut Option<SystemTime>) {
+    if mmdb.read().current().is_some() {
+        return;
+    }
+    let modified = match fs::metadata(mmdb_path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return,
+    };
+    if *attempted != Some(modified) {
+        *attempted = Some(modified);
+        reload(mmdb, mmdb_path);
+    }
 }
 
 fn terminate_channel() -> Result<Receiver<()>, String> {
@@ -148,3 +616,97 @@ fn reload_channel() -> Result<Receiver<()>, String> {
 
     Ok(receiver)
 }
+
+fn update_channel(mut updater: Updater, interval: Duration) -> Receiver<()> {
+    let (sender, receiver) = bounded(0);
+
+    thread::spawn(move || loop {
+        match updater.update() {
+            Ok(true) => {
+                let _ = sender.send(());
+            }
+            Ok(false) => {}
+            Err(err) => error!("failed to update mmdb, cause {}", err),
+        }
+        thread::sleep(interval);
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen(args: &[&str]) -> Vec<Listen> {
+        Opts::parse_from([&["mmdb-server"], args].concat()).listen()
+    }
+
+    #[test]
+    fn test_listen() {
+        let listens = listen(&[]);
+        assert_eq!(listens.len(), 1);
+        assert_eq!(listens[0].address, Address::Tcp("localhost:50000".to_string()));
+        assert!(listens[0].services.geoip && listens[0].services.health);
+        assert!(!listens[0].services.admin);
+
+        let listens = listen(&["--listen", "[::]:50000"]);
+        assert!(!listens[0].services.admin);
+
+        let listens = listen(&["--admin-listen", "127.0.0.1:50001,services=geoip,tls=off"]);
+        assert_eq!(listens.len(), 2);
+        assert!(!listens[0].services.admin);
+        assert_eq!(listens[1].address, Address::Tcp("127.0.0.1:50001".to_string()));
+        assert_eq!(listens[1].services, Services::admin());
+        assert_eq!(listens[1].security, Security::Plaintext);
+
+        let listens = listen(&["--listen", "127.0.0.1:50001,services=admin"]);
+        assert_eq!(listens[0].services, Services::admin());
+
+        // As given in MMDB_LISTEN, where the listeners are separated by `;` as their options use `,`.
+        let listens = listen(&["--listen", "[::]:50000,tls=off;unix:///run/mmdb.sock"]);
+        assert_eq!(listens.len(), 2);
+        assert_eq!(listens[0].security, Security::Plaintext);
+        assert_eq!(listens[1].address, Address::Unix("/run/mmdb.sock".into()));
+    }
+
+    #[test]
+    fn test_validate() {
+        let validate = |args: &[&str]| validate(&Opts::parse_from([&["mmdb-server"], args].concat()));
+        assert!(validate(&[]).is_ok());
+        for args in [
+            &["--workers", "0"][..],
+            &["--account-id", "1"],
+            &["--update-interval", "0s"],
+            &["--rate-limit-key", "identity"],
+            &["--trace-sample-ratio", "1.5"],
+            &["--listen", "[::]:50000", "--listen", "[::]:50000,tls=off"],
+            &["--listen", "127.0.0.1:50001", "--admin-listen", "127.0.0.1:50001"],
+        ] {
+            assert!(matches!(validate(args), Err(StartupError::Invalid(_))), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let file = |kind| StartupError::File("key", "tls.key".to_string(), io::Error::from(kind));
+        assert_eq!(StartupError::Invalid(String::new()).exit_code(), 78);
+        assert_eq!(file(io::ErrorKind::NotFound).exit_code(), 66);
+        assert_eq!(file(io::ErrorKind::PermissionDenied).exit_code(), 77);
+        assert_eq!(file(io::ErrorKind::InvalidData).exit_code(), 65);
+        assert_eq!(file(io::ErrorKind::Other).exit_code(), 74);
+        assert_eq!(StartupError::Bind(String::new(), String::new()).exit_code(), 75);
+
+        let err = tls::server_credentials(
+            &TlsFiles {
+                cert: file!().into(),
+                key: "/nonexistent/tls.key".into(),
+                client_ca: None,
+            },
+            &CertificateReloader::default(),
+        )
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!((err.what, err.source.kind()), ("key", io::ErrorKind::NotFound));
+    }
+}
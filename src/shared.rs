@@ -0,0 +1,155 @@
+use crate::database::Version;
+use crate::proto::geoip2::{CityReply, Empty, Message, MetadataReply};
+use crate::proto::geoip2_grpc::GeoIp;
+use crate::proto::lookup::LookupReply;
+use crate::CityService;
+use grpcio::{pb_de, pb_ser, GrpcSlice, Marshaller, MessageReader, Method, MethodType, Service, ServiceBuilder};
+use maxminddb::MaxMindDBError;
+use protobuf::{CodedOutputStream, Message as _};
+use std::sync::Arc;
+
+/// The number of the `city` field of `LookupReply`.
+const CITY_FIELD: u32 = 2;
+
+const METHOD_GEO_IP_LOOKUP: Method<Message, Arc<CityReply>> = Method {
+    ty: MethodType::Unary,
+    name: "/geoip2.GeoIp/Lookup",
+    req_mar: Marshaller { ser: pb_ser, de: pb_de },
+    resp_mar: Marshaller {
+        ser: ser_shared,
+        de: de_shared,
+    },
+};
+
+const METHOD_GEO_IP_METADATA: Method<Empty, MetadataReply> = Method {
+    ty: MethodType::Unary,
+    name: "/geoip2.GeoIp/Metadata",
+    req_mar: Marshaller { ser: pb_ser, de: pb_de },
+    resp_mar: Marshaller { ser: pb_ser, de: pb_de },
+};
+
+const METHOD_GEO_IP_RELOAD: Method<Empty, MetadataReply> = Method {
+    ty: MethodType::Unary,
+    name: "/geoip2.GeoIp/Reload",
+    req_mar: Marshaller { ser: pb_ser, de: pb_de },
+    resp_mar: Marshaller { ser: pb_ser, de: pb_de },
+};
+
+const METHOD_LOOKUP_LOOKUP: Method<Message, LookupAnswer> = Method {
+    ty: MethodType::Unary,
+    name: "/lookup.Lookup/Lookup",
+    req_mar: Marshaller { ser: pb_ser, de: pb_de },
+    resp_mar: Marshaller {
+        ser: ser_answer,
+        de: de_answer,
+    },
+};
+
+/// A `lookup.Lookup` reply whose city is shared with the reply cache instead of being moved into it.
+pub(crate) struct LookupAnswer {
+    /// The reply without its city.
+    pub reply: LookupReply,
+    pub city: Option<Arc<CityReply>>,
+}
+
+/// The `geoip2.GeoIp` service, as `geoip2_grpc::create_geo_ip` makes it but sending the lookup replies held by
+/// the reply cache without copying them.
+pub fn create_geo_ip<T, R>(service: CityService<T, R>) -> Service
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<Version<T>, MaxMindDBError>,
+    CityService<T, R>: Send + Clone + 'static,
+{
+    let mut builder = ServiceBuilder::new();
+    let mut instance = service.clone();
+    builder = builder.add_unary_handler(&METHOD_GEO_IP_LOOKUP, move |ctx, req, resp| {
+        instance.lookup_shared(ctx, req, resp)
+    });
+    let mut instance = service.clone();
+    builder = builder.add_unary_handler(&METHOD_GEO_IP_METADATA, move |ctx, req, resp| {
+        instance.metadata(ctx, req, resp)
+    });
+    let mut instance = service;
+    builder = builder.add_unary_handler(&METHOD_GEO_IP_RELOAD, move |ctx, req, resp| {
+        instance.reload(ctx, req, resp)
+    });
+    builder.build()
+}
+
+/// The `lookup.Lookup` service, sending the cities held by the reply cache without copying them.
+pub fn create_lookup<T, R>(service: CityService<T, R>) -> Service
+where
+    T: AsRef<[u8]>,
+    R: Fn() -> Result<Version<T>, MaxMindDBError>,
+    CityService<T, R>: Send + Clone + 'static,
+{
+    let mut instance = service;
+    ServiceBuilder::new()
+        .add_unary_handler(&METHOD_LOOKUP_LOOKUP, move |ctx, req, resp| {
+            instance.lookup_found(ctx, req, resp)
+        })
+        .build()
+}
+
+fn ser_shared(reply: &Arc<CityReply>, buf: &mut GrpcSlice) -> grpcio::Result<()> {
+    pb_ser(&**reply, buf)
+}
+
+fn de_shared(reader: MessageReader) -> grpcio::Result<Arc<CityReply>> {
+    pb_de(reader).map(Arc::new)
+}
+
+/// Writes the city after the other fields, as a parser takes the fields of a message in any order.
+fn ser_answer(answer: &LookupAnswer, buf: &mut GrpcSlice) -> grpcio::Result<()> {
+    let mut bytes = answer.reply.write_to_bytes()?;
+    if let Some(ref city) = answer.city {
+        let mut os = CodedOutputStream::vec(&mut bytes);
+        os.write_message(CITY_FIELD, &**city)?;
+        os.flush()?;
+    }
+    *buf = GrpcSlice::from(bytes);
+    Ok(())
+}
+
+fn de_answer(reader: MessageReader) -> grpcio::Result<LookupAnswer> {
+    let mut reply: LookupReply = pb_de(reader)?;
+    let city = if reply.has_city() {
+        Some(Arc::new(reply.take_city()))
+    } else {
+        None
+    };
+    Ok(LookupAnswer { reply, city })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ser_answer() {
+        let mut city = CityReply::default();
+        city.mut_country().set_iso_code("JP".to_string());
+        let mut reply = LookupReply::default();
+        reply.set_found(true);
+        let answer = LookupAnswer {
+            reply,
+            city: Some(Arc::new(city.clone())),
+        };
+
+        let mut buf = GrpcSlice::default();
+        ser_answer(&answer, &mut buf).unwrap();
+        let parsed = LookupReply::parse_from_bytes(buf.as_slice()).unwrap();
+        assert!(parsed.get_found());
+        assert_eq!(parsed.get_city(), &city);
+
+        let answer = LookupAnswer {
+            reply: LookupReply::default(),
+            city: None,
+        };
+        ser_answer(&answer, &mut buf).unwrap();
+        assert_eq!(
+            LookupReply::parse_from_bytes(buf.as_slice()).unwrap(),
+            LookupReply::default()
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use crate::proto::error_details::ErrorInfo;
+use crate::proto::status::Status;
+use grpcio::{RpcStatus, RpcStatusCode};
+use protobuf::well_known_types::Any;
+use protobuf::{Message, RepeatedField};
+
+/// The `ErrorInfo` domain of the errors raised by the services.
+pub(crate) const DOMAIN: &str = "mmdb-grpc";
+
+/// Wraps `message` in an `Any` the way other gRPC implementations expect to unpack it.
+pub(crate) fn pack<M: Message>(message: &M) -> Any {
+    let mut any = Any::new();
+    any.set_type_url(format!("type.googleapis.com/{}", message.descriptor().full_name()));
+    any.set_value(message.write_to_bytes().unwrap_or_default());
+    any
+}
+
+/// The `ErrorInfo` in the details of `status`, if any.
+pub(crate) fn error_info(status: &RpcStatus) -> Option<ErrorInfo> {
+    let details = Status::parse_from_bytes(status.details()).ok()?;
+    let type_url = format!("type.googleapis.com/{}", ErrorInfo::descriptor_static().full_name());
+    details
+        .get_details()
+        .iter()
+        .find(|any| any.get_type_url() == type_url)
+        .and_then(|any| ErrorInfo::parse_from_bytes(any.get_value()).ok())
+}
+
+/// Builds a status carrying a `google.rpc.Status` with `details` in its `grpc-status-details-bin` trailer.
+pub(crate) fn with_details(code: RpcStatusCode, message: String, details: Vec<Any>) -> RpcStatus {
+    let mut status = Status::new();
+    status.set_code(code.into());
+    status.set_message(message.clone());
+    status.set_details(RepeatedField::from_vec(details));
+    RpcStatus::with_details(code, message, status.write_to_bytes().unwrap_or_default())
+}
+
+/// Builds a status carrying an `ErrorInfo`, so that clients can tell errors apart by `reason` and read what
+/// they were about from `metadata` rather than from the message.
+pub(crate) fn with_reason(
+    code: RpcStatusCode,
+    message: String,
+    reason: &str,
+    metadata: Vec<(&str, String)>,
+) -> RpcStatus {
+    let mut info = ErrorInfo::new();
+    info.set_reason(reason.to_string());
+    info.set_domain(DOMAIN.to_string());
+    info.set_metadata(
+        metadata
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    );
+    with_details(code, message, vec![pack(&info)])
+}
@@ -0,0 +1,140 @@
+use grpcio::{
+    CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials, ServerCredentialsBuilder,
+    ServerCredentialsFetcher,
+};
+use log::{error, info};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A PEM file that could not be read, `what` telling which one it is.
+#[derive(Debug)]
+pub struct TlsError {
+    pub what: &'static str,
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to read the {} {}: {}",
+            self.what,
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn read(what: &'static str, path: &Path) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|source| TlsError {
+        what,
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// PEM files the server presents and, for mutual TLS, verifies clients against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    fn builder(&self) -> Result<ServerCredentialsBuilder, TlsError> {
+        let mut builder =
+            ServerCredentialsBuilder::new().add_cert(read("certificate", &self.cert)?, read("key", &self.key)?);
+        if let Some(ref ca) = self.client_ca {
+            builder = builder.root_cert(
+                read("client CA", ca)?,
+                CertificateRequestType::RequestAndRequireClientCertificateAndVerify,
+            );
+        }
+        Ok(builder)
+    }
+
+    fn request_type(&self) -> CertificateRequestType {
+        if self.client_ca.is_some() {
+            CertificateRequestType::RequestAndRequireClientCertificateAndVerify
+        } else {
+            CertificateRequestType::DontRequestClientCertificate
+        }
+    }
+}
+
+/// Makes the servers pick up the certificate files again on their next connection.
+#[derive(Clone, Default)]
+pub struct CertificateReloader(Arc<AtomicUsize>);
+
+impl CertificateReloader {
+    pub fn reload(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+struct Fetcher {
+    files: TlsFiles,
+    generation: Arc<AtomicUsize>,
+    loaded: AtomicUsize,
+}
+
+impl ServerCredentialsFetcher for Fetcher {
+    fn fetch(&self) -> Result<Option<ServerCredentialsBuilder>, Box<dyn Error>> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        if self.loaded.swap(generation, Ordering::SeqCst) == generation {
+            return Ok(None);
+        }
+        match self.files.builder() {
+            Ok(builder) => {
+                info!("loaded the certificate {}", self.files.cert.display());
+                Ok(Some(builder))
+            }
+            Err(err) => {
+                error!("keep the current certificate, cause {}", err);
+                Err(Box::new(err))
+            }
+        }
+    }
+}
+
+/// Builds credentials that re-read `files` whenever `reloader` is triggered.
+pub fn server_credentials(files: &TlsFiles, reloader: &CertificateReloader) -> Result<ServerCredentials, TlsError> {
+    // Fail fast on unreadable files rather than when the first connection is accepted.
+    files.builder()?;
+
+    let fetcher = Fetcher {
+        files: files.clone(),
+        generation: reloader.0.clone(),
+        loaded: AtomicUsize::new(usize::MAX),
+    };
+    Ok(ServerCredentials::with_fetcher(Box::new(fetcher), files.request_type()))
+}
+
+/// Builds client credentials trusting `ca`, presenting `cert` and `key` when the server requires them.
+pub fn channel_credentials<P: AsRef<Path>>(
+    ca: Option<P>,
+    cert: Option<P>,
+    key: Option<P>,
+) -> Result<ChannelCredentials, TlsError> {
+    let mut builder = ChannelCredentialsBuilder::new();
+    if let Some(ca) = ca {
+        builder = builder.root_cert(read("CA", ca.as_ref())?);
+    }
+    if let (Some(cert), Some(key)) = (cert, key) {
+        builder = builder.cert(read("certificate", cert.as_ref())?, read("key", key.as_ref())?);
+    }
+    Ok(builder.build())
+}
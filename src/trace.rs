@@ -0,0 +1,483 @@
+use crate::metrics;
+use crossbeam_channel::{bounded, select, tick, Receiver, Sender};
+use grpcio::RpcContext;
+use log::{error, warn};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BATCH_SIZE: usize = 512;
+const QUEUE_SIZE: usize = 4096;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// The W3C trace context propagated in the `traceparent` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parses `00-<trace-id>-<parent-id>-<flags>`, rejecting all-zero ids as the spec requires.
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let span_id = fields.next()?;
+        let flags = fields.next()?;
+        if version.len() != 2 || version == "ff" || trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        // Later versions may append fields, version 00 must not.
+        if version == "00" && fields.next().is_some() {
+            return None;
+        }
+        u8::from_str_radix(version, 16).ok()?;
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            sampled: flags & 0x01 == 0x01,
+        })
+    }
+
+    /// Reads the `traceparent` header of a call.
+    pub fn extract(ctx: &RpcContext<'_>) -> Option<TraceContext> {
+        ctx.request_headers()
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("traceparent"))
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+            .and_then(TraceContext::parse)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Internal = 1,
+    Server = 2,
+}
+
+#[derive(Clone, Debug)]
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: String,
+    kind: Kind,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: Option<String>,
+}
+
+/// A span exported when dropped, if tracing is enabled and the trace is sampled.
+pub struct Span {
+    data: Option<SpanData>,
+}
+
+impl Span {
+    /// Starts a span for a call, continuing the trace of `parent` when the caller sent one.
+    pub fn server(name: &str, parent: Option<TraceContext>) -> Span {
+        Span::start(name, Kind::Server, parent)
+    }
+
+    /// Starts a span for work that is not a call, such as a reload on `SIGHUP`.
+    pub fn internal(name: &str) -> Span {
+        Span::start(name, Kind::Internal, None)
+    }
+
+    fn start(name: &str, kind: Kind, parent: Option<TraceContext>) -> Span {
+        let exporter = match EXPORTER.get() {
+            Some(exporter) => exporter,
+            None => return Span { data: None },
+        };
+        // Follows the decision of the caller, and samples the traces started here by ratio.
+        let (trace_id, sampled) = match parent {
+            Some(parent) => (parent.trace_id, parent.sampled),
+            None => {
+                let trace_id = (u128::from(random_id()) << 64) | u128::from(random_id());
+                (trace_id, sample(trace_id, exporter.sample_ratio))
+            }
+        };
+        if !sampled {
+            return Span { data: None };
+        }
+        Span {
+            data: Some(SpanData {
+                trace_id,
+                span_id: random_id(),
+                parent_span_id: parent.map(|parent| parent.span_id),
+                name: name.to_string(),
+                kind,
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// Starts a span for a step of this one.
+    pub fn child(&self, name: &str) -> Span {
+        match self.data {
+            Some(ref data) => Span::start(
+                name,
+                Kind::Internal,
+                Some(TraceContext {
+                    trace_id: data.trace_id,
+                    span_id: data.span_id,
+                    sampled: true,
+                }),
+            ),
+            None => Span { data: None },
+        }
+    }
+
+    pub fn set_attribute<V: ToString>(&mut self, key: &'static str, value: V) {
+        if let Some(ref mut data) = self.data {
+            data.attributes.push((key, value.to_string()));
+        }
+    }
+
+    /// Sets `net.sock.peer.addr` and `net.sock.peer.port` from a peer such as `ipv4:192.0.2.1:5678`, as
+    /// grpcio formats it.
+    pub fn set_peer(&mut self, peer: &str) {
+        if self.data.is_none() {
+            return;
+        }
+        let (addr, port) = peer_address(peer);
+        self.set_attribute("net.sock.peer.addr", addr);
+        if let Some(port) = port {
+            self.set_attribute("net.sock.peer.port", port);
+        }
+    }
+
+    pub fn set_error<M: ToString>(&mut self, message: M) {
+        if let Some(ref mut data) = self.data {
+            data.error = Some(message.to_string());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let (Some(mut data), Some(exporter)) = (self.data.take(), EXPORTER.get()) {
+            data.end = SystemTime::now();
+            exporter.export(data);
+        }
+    }
+}
+
+/// Whether to sample a trace, keeping `ratio` of them by their id like OpenTelemetry's `TraceIdRatioBased`.
+fn sample(trace_id: u128, ratio: f64) -> bool {
+    let bound = (ratio.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+    // The lower half of the id, which is random even in ids of other generators.
+    ratio >= 1.0 || (trace_id as u64) < bound
+}
+
+/// Splits `ipv4:192.0.2.1:5678`, `ipv6:[2001:db8::1]:5678` or `unix:/run/mmdb.sock` into the address and port.
+pub(crate) fn peer_address(peer: &str) -> (&str, Option<u16>) {
+    let address = match peer.split_once(':') {
+        Some(("ipv4", address)) | Some(("ipv6", address)) => address,
+        Some(("unix", path)) => return (path, None),
+        _ => return (peer, None),
+    };
+    match address.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host.trim_start_matches('[').trim_end_matches(']'), Some(port)),
+            Err(_) => (address, None),
+        },
+        None => (address, None),
+    }
+}
+
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+enum Command {
+    Export(SpanData),
+    Flush(Sender<()>),
+}
+
+/// Sends finished spans in batches to an OTLP/HTTP collector from a background thread.
+struct Exporter {
+    sender: Sender<Command>,
+    sample_ratio: f64,
+    /// Spans dropped since the exporter last warned about it.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Exporter {
+    fn start(endpoint: &str, service_name: &str, sample_ratio: f64) -> Exporter {
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let service_name = service_name.to_string();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counted = dropped.clone();
+        thread::spawn(move || run(receiver, &url, &service_name, &counted));
+        Exporter {
+            sender,
+            sample_ratio,
+            dropped,
+        }
+    }
+
+    /// Queues a span, or drops it when the exporter is behind, which is only logged once per interval.
+    fn export(&self, span: SpanData) {
+        if self.sender.try_send(Command::Export(span)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            metrics::observe_dropped_span();
+        }
+    }
+
+    fn flush(&self, timeout: Duration) {
+        let (sender, receiver) = bounded(1);
+        if self.sender.send_timeout(Command::Flush(sender), timeout).is_ok() {
+            let _ = receiver.recv_timeout(timeout);
+        }
+    }
+}
+
+fn run(receiver: Receiver<Command>, url: &str, service_name: &str, dropped: &AtomicU64) {
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+    let ticker = tick(EXPORT_INTERVAL);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        select! {
+            recv(receiver) -> command => match command {
+                Ok(Command::Export(span)) => {
+                    batch.push(span);
+                    if batch.len() >= BATCH_SIZE {
+                        send(&agent, url, service_name, &mut batch);
+                    }
+                }
+                Ok(Command::Flush(done)) => {
+                    send(&agent, url, service_name, &mut batch);
+                    let _ = done.send(());
+                }
+                Err(_) => return,
+            },
+            recv(ticker) -> _ => {
+                send(&agent, url, service_name, &mut batch);
+                let n = dropped.swap(0, Ordering::Relaxed);
+                if n > 0 {
+                    warn!("dropped {} spans in the last {:?}, the exporter queue is full", n, EXPORT_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+fn send(agent: &ureq::Agent, url: &str, service_name: &str, batch: &mut Vec<SpanData>) {
+    if batch.is_empty() {
+        return;
+    }
+    let body = encode(service_name, batch);
+    batch.clear();
+    let result = agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string());
+    if let Err(err) = result {
+        error!("failed to export spans to {}, cause {}", url, err);
+    }
+}
+
+/// Encodes spans as an OTLP/JSON `ExportTraceServiceRequest`.
+fn encode(service_name: &str, spans: &[SpanData]) -> Value {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let mut value = json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": span.attributes.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+                "status": match span.error {
+                    Some(ref message) => json!({ "code": 2, "message": message }),
+                    None => json!({ "code": 0 }),
+                },
+            });
+            if let Some(parent) = span.parent_span_id {
+                value["parentSpanId"] = json!(format!("{:016x}", parent));
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [attribute("service.name", service_name)] },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+// OTLP/JSON encodes 64 bit integers as strings.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Exports spans to the OTLP/HTTP collector at `endpoint`, such as `http://localhost:4318`, sampling
+/// `sample_ratio` of the traces that do not come with a sampling decision from the caller.
+///
+/// Spans are not recorded until this is called, and only the first call has an effect.
+pub fn init(endpoint: &str, service_name: &str, sample_ratio: f64) {
+    EXPORTER.get_or_init(|| Exporter::start(endpoint, service_name, sample_ratio));
+}
+
+/// Waits up to `timeout` for the spans recorded so far to be sent.
+pub fn flush(timeout: Duration) {
+    if let Some(exporter) = EXPORTER.get() {
+        exporter.flush(timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some(TraceContext {
+                trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                span_id: 0x00f067aa0ba902b7,
+                sampled: true
+            })
+        );
+        assert_eq!(
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").map(|c| c.sampled),
+            Some(false)
+        );
+        assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01").is_none());
+        assert!(TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x").is_none());
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x").is_some());
+        assert!(TraceContext::parse("4bf92f3577b34da6a3ce929d0e0e4736").is_none());
+    }
+
+    #[test]
+    fn test_sample() {
+        assert!(sample(u128::MAX, 1.0));
+        assert!(!sample(0, 0.0));
+        assert!(sample(u128::from(u64::MAX / 4), 0.5));
+        assert!(!sample(u128::from(u64::MAX / 4 * 3), 0.5));
+        // Only the lower half counts.
+        assert!(sample(u128::MAX << 64, 0.01));
+
+        let sampled = (0..10000).filter(|_| sample(u128::from(random_id()), 0.1)).count();
+        assert!((800..1200).contains(&sampled), "{}", sampled);
+    }
+
+    #[test]
+    fn test_peer_address() {
+        assert_eq!(peer_address("ipv4:192.0.2.1:5678"), ("192.0.2.1", Some(5678)));
+        assert_eq!(peer_address("ipv6:[2001:db8::1]:5678"), ("2001:db8::1", Some(5678)));
+        assert_eq!(peer_address("unix:/run/mmdb.sock"), ("/run/mmdb.sock", None));
+        assert_eq!(peer_address("unknown"), ("unknown", None));
+    }
+
+    // Accepts one export request like an OpenTelemetry collector does, handing over its body.
+    fn collector() -> (String, Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = bounded(1);
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line.trim(), "POST /v1/traces HTTP/1.1");
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{{}}").unwrap();
+            sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+        });
+        (format!("http://{}", addr), receiver)
+    }
+
+    #[test]
+    fn test_export() {
+        let (endpoint, received) = collector();
+        let exporter = Exporter::start(&endpoint, "mmdb-server", 1.0);
+        let now = SystemTime::now();
+        exporter.export(SpanData {
+            trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+            span_id: 0x1,
+            parent_span_id: Some(0x00f067aa0ba902b7),
+            name: "geoip2.GeoIp/Lookup".to_string(),
+            kind: Kind::Server,
+            start: now,
+            end: now,
+            attributes: vec![("net.sock.peer.addr", "192.0.2.1".to_string())],
+            error: Some("not found".to_string()),
+        });
+        exporter.flush(Duration::from_secs(5));
+
+        let body = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "mmdb-server"
+        );
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["spanId"], "0000000000000001");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["name"], "geoip2.GeoIp/Lookup");
+        assert_eq!(span["kind"], 2);
+        assert_eq!(span["attributes"][0]["key"], "net.sock.peer.addr");
+        assert_eq!(span["status"]["code"], 2);
+    }
+}
@@ -0,0 +1,153 @@
+use maxminddb::Metadata;
+use std::net::IpAddr;
+
+/// The search tree of a database, walked the way `maxminddb::Reader` does to tell which record an address
+/// is found in without decoding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SearchTree {
+    node_count: usize,
+    record_size: u16,
+    ipv4_start: usize,
+}
+
+impl SearchTree {
+    pub(crate) fn new(buf: &[u8], metadata: &Metadata) -> SearchTree {
+        let mut tree = SearchTree {
+            node_count: metadata.node_count as usize,
+            record_size: metadata.record_size,
+            ipv4_start: 0,
+        };
+        // IPv4 addresses are under ::/96 of an IPv6 tree.
+        if metadata.ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= tree.node_count {
+                    break;
+                }
+                // A malformed tree finds no IPv4 address, leaving the lookup to tell why.
+                node = tree.read(buf, node, 0).unwrap_or(tree.node_count);
+            }
+            tree.ipv4_start = node;
+        }
+        tree
+    }
+
+    /// The offset in the data section of the record `ip` is found in.
+    ///
+    /// `None` when the address is not in the tree or the tree is malformed, in which case the lookup tells why.
+    pub(crate) fn record(&self, buf: &[u8], ip: IpAddr) -> Option<usize> {
+        let (bits, bit_count, mut node) = match ip {
+            IpAddr::V4(v4) => (u128::from(u32::from(v4)), 32, self.ipv4_start),
+            IpAddr::V6(v6) => (u128::from(v6), 128, 0),
+        };
+        for i in 0..bit_count {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits >> (bit_count - 1 - i)) & 1;
+            node = self.read(buf, node, bit as usize)?;
+        }
+        // Pointers past the tree are into the data section, which follows a 16 bytes separator.
+        if node > self.node_count {
+            node.checked_sub(self.node_count + 16)
+        } else {
+            None
+        }
+    }
+
+    fn read(&self, buf: &[u8], node: usize, index: usize) -> Option<usize> {
+        let base = node * self.record_size as usize / 4;
+        let bytes = |offset: usize, len: usize| buf.get(offset..offset + len);
+        match self.record_size {
+            24 => bytes(base + index * 3, 3).map(|b| to_usize(0, b)),
+            28 => {
+                let middle = *buf.get(base + 3)?;
+                let middle = if index == 0 { middle >> 4 } else { middle & 0x0f };
+                bytes(base + index * 4, 3).map(|b| to_usize(middle, b))
+            }
+            32 => bytes(base + index * 4, 4).map(|b| to_usize(0, b)),
+            _ => None,
+        }
+    }
+}
+
+fn to_usize(base: u8, bytes: &[u8]) -> usize {
+    bytes.iter().fold(base as usize, |acc, &b| (acc << 8) | b as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use maxminddb::{geoip2, Reader};
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    fn tree(node_count: usize, record_size: u16, ipv4_start: usize) -> SearchTree {
+        SearchTree {
+            node_count,
+            record_size,
+            ipv4_start,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        // 0.0.0.0/1 and 128.0.0.0/2 share the first record, 192.0.0.0/2 is not in the tree.
+        let buf = [0x00, 0x00, 0x12, 0x00, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00, 0x00, 0x02];
+        let tree = tree(2, 24, 0);
+        assert_eq!(tree.record(&buf, "10.0.0.1".parse().unwrap()), Some(0));
+        assert_eq!(tree.record(&buf, "128.0.0.1".parse().unwrap()), Some(0));
+        assert_eq!(tree.record(&buf, "192.0.0.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_record_sizes() {
+        // A left record of 0x1234567 and a right one of 0x89abcde.
+        let buf = [0x23, 0x45, 0x67, 0x18, 0x9a, 0xbc, 0xde];
+        let tree28 = tree(1, 28, 0);
+        assert_eq!(tree28.read(&buf, 0, 0), Some(0x1234567));
+        assert_eq!(tree28.read(&buf, 0, 1), Some(0x89abcde));
+        assert_eq!(tree28.read(&buf, 1, 0), None);
+
+        let buf = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let tree32 = tree(1, 32, 0);
+        assert_eq!(tree32.read(&buf, 0, 0), Some(0x01020304));
+        assert_eq!(tree32.read(&buf, 0, 1), Some(0x05060708));
+    }
+
+    // The walk mirrors the private one of `maxminddb::Reader`, so it is checked against what the reader finds.
+    #[test]
+    fn test_record_matches_reader() {
+        let buf = fixture::database(1);
+        let reader = Reader::from_source(buf.as_slice()).unwrap();
+        let tree = SearchTree::new(&buf, &reader.metadata);
+
+        let mut cities = HashMap::new();
+        for ip in fixture::addresses(2000) {
+            let ip: IpAddr = ip.parse().unwrap();
+            let (city, prefix_len) = reader.lookup_prefix::<geoip2::City>(ip).unwrap();
+            let record = tree.record(&buf, ip).unwrap();
+            let geoname_id = city.city.unwrap().geoname_id.unwrap();
+            // The records of the cities are apart, so a record is one city and a city one record.
+            assert_eq!(*cities.entry(record).or_insert(geoname_id), geoname_id, "{}", ip);
+
+            // Every address of the network the reader found is in the same record.
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            let IpAddr::V4(v4) = ip else { unreachable!() };
+            for other in [u32::from(v4) & mask, u32::from(v4) | !mask] {
+                assert_eq!(
+                    tree.record(&buf, IpAddr::V4(Ipv4Addr::from(other))),
+                    Some(record),
+                    "{}",
+                    ip
+                );
+            }
+        }
+        assert_eq!(cities.len(), fixture::RECORDS);
+        let mut ids: Vec<_> = cities.values().collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), fixture::RECORDS);
+    }
+}
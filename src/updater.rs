@@ -0,0 +1,350 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use log::{debug, info};
+use maxminddb::MaxMindDBError;
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_ENDPOINT: &str = "https://download.maxmind.com";
+
+/// The most memory set aside for a database before reading it out of an archive, above the size of a city one.
+const MAX_PREALLOCATED: u64 = 256 << 20;
+
+#[derive(Debug)]
+pub enum UpdateError {
+    Http(String),
+    Io(io::Error),
+    Checksum { expected: String, actual: String },
+    MissingDatabase(String),
+    InvalidDatabase(MaxMindDBError),
+}
+
+impl Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Http(msg) => write!(f, "failed to download: {}", msg),
+            UpdateError::Io(err) => write!(f, "failed to write the database: {}", err),
+            UpdateError::Checksum { expected, actual } => {
+                write!(f, "checksum mismatch, expected {} but got {}", expected, actual)
+            }
+            UpdateError::MissingDatabase(edition) => write!(f, "no .mmdb file in the {} archive", edition),
+            UpdateError::InvalidDatabase(err) => write!(f, "downloaded database is invalid: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+impl From<io::Error> for UpdateError {
+    fn from(err: io::Error) -> UpdateError {
+        UpdateError::Io(err)
+    }
+}
+
+impl From<ureq::Error> for UpdateError {
+    fn from(err: ureq::Error) -> UpdateError {
+        UpdateError::Http(err.to_string())
+    }
+}
+
+/// Downloads an edition from a MaxMind compatible endpoint and installs it at `path`.
+///
+/// The digests of the installed archive and database are kept in `<path>.sha256`, so that a restarted
+/// server does not download the same archive again.
+pub struct Updater {
+    agent: ureq::Agent,
+    endpoint: String,
+    authorization: String,
+    edition_id: String,
+    path: PathBuf,
+    installed: Option<String>,
+}
+
+impl Updater {
+    pub fn new<P: AsRef<Path>>(
+        endpoint: &str,
+        account_id: &str,
+        license_key: &str,
+        edition_id: &str,
+        path: P,
+    ) -> Updater {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(60))
+            .build();
+        let credentials = STANDARD.encode(format!("{}:{}", account_id, license_key));
+        let installed = installed(path.as_ref());
+        if let Some(ref digest) = installed {
+            debug!("{} was installed from the archive {}", path.as_ref().display(), digest);
+        }
+        Updater {
+            agent,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            authorization: format!("Basic {}", credentials),
+            edition_id: edition_id.to_string(),
+            path: path.as_ref().to_path_buf(),
+            installed,
+        }
+    }
+
+    /// Installs the latest database unless it is the one installed last time.
+    ///
+    /// Returns `true` when the file at `path` has been replaced and should be reloaded.
+    pub fn update(&mut self) -> Result<bool, UpdateError> {
+        let expected = self.fetch_checksum()?;
+        if self.installed.as_ref() == Some(&expected) {
+            debug!("{} is up to date", self.edition_id);
+            return Ok(false);
+        }
+
+        let archive = self.fetch("tar.gz")?;
+        let actual = format!("{:x}", Sha256::digest(&archive));
+        if actual != expected {
+            return Err(UpdateError::Checksum { expected, actual });
+        }
+
+        let db = self.extract(&archive)?;
+        maxminddb::Reader::from_source(&db[..]).map_err(UpdateError::InvalidDatabase)?;
+        self.install(&db, &expected)?;
+
+        info!("installed {} into {}", self.edition_id, self.path.display());
+        self.installed = Some(expected);
+        Ok(true)
+    }
+
+    fn url(&self, suffix: &str) -> String {
+        format!(
+            "{}/geoip/databases/{}/download?suffix={}",
+            self.endpoint, self.edition_id, suffix
+        )
+    }
+
+    fn fetch(&self, suffix: &str) -> Result<Vec<u8>, UpdateError> {
+        let resp = self
+            .agent
+            .get(&self.url(suffix))
+            .set("Authorization", &self.authorization)
+            .call()?;
+        let mut buf = Vec::new();
+        resp.into_reader().read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// The sidecar is formatted as `sha256sum` prints it, i.e. `<digest>  <file name>`.
+    fn fetch_checksum(&self) -> Result<String, UpdateError> {
+        let body = self.fetch("tar.gz.sha256")?;
+        String::from_utf8_lossy(&body)
+            .split_whitespace()
+            .next()
+            .map(|s| s.to_ascii_lowercase())
+            .ok_or_else(|| UpdateError::Http("empty checksum".to_string()))
+    }
+
+    fn extract(&self, archive: &[u8]) -> Result<Vec<u8>, UpdateError> {
+        let mut entries = tar::Archive::new(GzDecoder::new(archive));
+        for entry in entries.entries()? {
+            let mut entry = entry?;
+            let is_mmdb = entry.path()?.extension().is_some_and(|ext| ext == "mmdb");
+            if is_mmdb {
+                // The size comes from the archive, so a corrupt one must not make us set aside more than a
+                // database takes.
+                let mut buf = Vec::with_capacity(entry.size().min(MAX_PREALLOCATED) as usize);
+                entry.read_to_end(&mut buf)?;
+                return Ok(buf);
+            }
+        }
+        Err(UpdateError::MissingDatabase(self.edition_id.clone()))
+    }
+
+    fn install(&self, db: &[u8], archive_digest: &str) -> Result<(), UpdateError> {
+        write_synced(&self.path, db)?;
+        let digests = format!("{}  {:x}\n", archive_digest, Sha256::digest(db));
+        write_synced(&sidecar(&self.path), digests.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn sidecar(path: &Path) -> PathBuf {
+    let mut sidecar = path.to_path_buf().into_os_string();
+    sidecar.push(".sha256");
+    PathBuf::from(sidecar)
+}
+
+/// The digest of the archive the database at `path` was installed from, unless the file changed since.
+fn installed(path: &Path) -> Option<String> {
+    let digests = fs::read_to_string(sidecar(path)).ok()?;
+    let mut digests = digests.split_whitespace();
+    let (archive, db) = (digests.next()?, digests.next()?);
+    let actual = format!("{:x}", Sha256::digest(fs::read(path).ok()?));
+    (actual == db).then(|| archive.to_string())
+}
+
+/// Writes next to the destination first so that the rename replaces it atomically, and flushes both the file
+/// and the directory so that a crash leaves either the old or the new contents.
+fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::database;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn archive(db: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(db.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "GeoLite2-City_20240101/GeoLite2-City.mmdb", db)
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    // Serves the archive and its sidecar for `requests` requests, like download.maxmind.com does.
+    fn serve(archive: Vec<u8>, checksum: String, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                reader.read_line(&mut line).unwrap();
+                let mut authorized = false;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    authorized |= header.trim() == "Authorization: Basic NDI6c2VjcmV0";
+                }
+                let (status, body) = if !authorized {
+                    ("401 Unauthorized", Vec::new())
+                } else if line.contains("suffix=tar.gz.sha256") {
+                    (
+                        "200 OK",
+                        format!("{}  GeoLite2-City_20240101.tar.gz\n", checksum).into_bytes(),
+                    )
+                } else {
+                    ("200 OK", archive.clone())
+                };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_update() {
+        let db = database(1_700_000_000);
+        let archive = archive(&db);
+        let checksum = format!("{:x}", Sha256::digest(&archive));
+        let endpoint = serve(archive, checksum, 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        let mut updater = Updater::new(&endpoint, "42", "secret", "GeoLite2-City", &path);
+
+        assert!(updater.update().unwrap());
+        assert_eq!(fs::read(&path).unwrap(), db);
+        assert!(!updater.update().unwrap());
+    }
+
+    #[test]
+    fn test_update_after_restart() {
+        let db = database(1_700_000_000);
+        let archive = archive(&db);
+        let checksum = format!("{:x}", Sha256::digest(&archive));
+        let endpoint = serve(archive, checksum, 5);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        assert!(Updater::new(&endpoint, "42", "secret", "GeoLite2-City", &path)
+            .update()
+            .unwrap());
+
+        // Only the checksum is downloaded again.
+        let mut restarted = Updater::new(&endpoint, "42", "secret", "GeoLite2-City", &path);
+        assert!(!restarted.update().unwrap());
+
+        // A database replaced by hand is not the installed one anymore.
+        fs::write(&path, b"replaced").unwrap();
+        let mut restarted = Updater::new(&endpoint, "42", "secret", "GeoLite2-City", &path);
+        assert!(restarted.update().unwrap());
+        assert_eq!(fs::read(&path).unwrap(), db);
+    }
+
+    #[test]
+    fn test_extract_truncated() {
+        // A header claiming a terabyte followed by a few bytes, which are all there is to read.
+        let mut header = tar::Header::new_gnu();
+        header.set_path("GeoLite2-City.mmdb").unwrap();
+        header.set_size(1 << 40);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(header.as_bytes()).unwrap();
+        encoder.write_all(&[0; 512]).unwrap();
+        let archive = encoder.finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        let updater = Updater::new(DEFAULT_ENDPOINT, "42", "secret", "GeoLite2-City", &path);
+        let db = updater.extract(&archive).unwrap();
+        assert!(db.capacity() <= MAX_PREALLOCATED as usize, "{}", db.capacity());
+    }
+
+    #[test]
+    fn test_update_checksum_mismatch() {
+        let archive = archive(&database(1_700_000_000));
+        let endpoint = serve(archive, "0".repeat(64), 2);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        let mut updater = Updater::new(&endpoint, "42", "secret", "GeoLite2-City", &path);
+
+        match updater.update() {
+            Err(UpdateError::Checksum { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_update_unauthorized() {
+        let endpoint = serve(Vec::new(), String::new(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        let mut updater = Updater::new(&endpoint, "42", "wrong", "GeoLite2-City", &path);
+
+        match updater.update() {
+            Err(UpdateError::Http(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}